@@ -0,0 +1,63 @@
+//! cleanup
+
+use msft_runtime::{
+    common::{ThreadpoolCleanupGroup, ThreadpoolHandle, WaitPending},
+    timer::{TimerPool, TimerThreadpoolOptions},
+    work::WorkOncePool,
+};
+use std::{io, time::Duration};
+use tracing::info;
+use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, prelude::*};
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    // Setup logging
+    let stdout = fmt::layer()
+        .compact()
+        .with_ansi(true)
+        .with_level(true)
+        .with_file(false)
+        .with_line_number(false)
+        .with_target(true);
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(LevelFilter::TRACE)
+        .init();
+
+    // Print welcome message
+    info!("Starting cleanup group demo");
+
+    // Create a private threadpool and a cleanup group. Every pool created with the environment is
+    // a member of the group
+    let threadpool = ThreadpoolHandle::new()?;
+    threadpool.min_threads(1)?.max_threads(4);
+    let group = ThreadpoolCleanupGroup::new();
+    let env = threadpool.new_environment().with_group(&group);
+
+    // Create a timer and some work in the group
+    let opts = TimerThreadpoolOptions {
+        env: Some(&env),
+        ..Default::default()
+    };
+    let mut timer = TimerPool::new(&opts)?;
+    let work = WorkOncePool::with_environment(&env, |_| 42)?.submit_once();
+
+    // Wait for the work and the timer
+    let answer = work.future().await;
+    info!(answer, "work complete");
+    timer
+        .oneshot(Duration::from_millis(500))
+        .await
+        .start()
+        .await;
+    info!("timer complete");
+
+    // Hand the members to the group and tear them all down at once
+    timer.into_group_member(&group);
+    work.into_group_member(&group);
+    group.close_members(WaitPending::Cancel);
+
+    // Print exit message
+    info!("all done");
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! cobs
+
+use super::Decode;
+use bytes::{Buf, BufMut, BytesMut};
+use std::{error, fmt};
+
+/// A frame could not be unstuffed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CobsError {
+    /// A code byte points past the end of the frame
+    InvalidStuffing,
+}
+
+impl fmt::Display for CobsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CobsError::InvalidStuffing => write!(f, "invalid cobs stuffing"),
+        }
+    }
+}
+
+impl error::Error for CobsError {}
+
+/// A [`Decode`] implementation for Consistent Overhead Byte Stuffing. Frames are delimited by a
+/// zero byte, and the decoded frames do not include the delimiter.
+///
+/// [See also](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing)
+#[derive(Default)]
+pub struct CobsDecoder {
+    // Current index into the buffer so we avoid re-scanning the buffer each call to decode
+    index: usize,
+}
+
+impl Decode for CobsDecoder {
+    type Item = BytesMut;
+    type Error = CobsError;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            match src[self.index..].iter().position(|b| *b == 0) {
+                Some(offset) => {
+                    let len = self.index + offset;
+                    self.index = 0;
+                    if len == 0 {
+                        // Skip empty frames between consecutive delimiters
+                        src.advance(1);
+                        continue;
+                    }
+                    let frame = src.split_to(len);
+                    src.advance(1);
+                    return unstuff(&frame).map(Some);
+                }
+                None => {
+                    self.index = src.len();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+fn unstuff(src: &[u8]) -> Result<BytesMut, CobsError> {
+    let mut dst = BytesMut::with_capacity(src.len());
+    let mut i = 0;
+    while i < src.len() {
+        let code = src[i] as usize;
+        if i + code > src.len() {
+            return Err(CobsError::InvalidStuffing);
+        }
+        dst.put_slice(&src[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < src.len() {
+            dst.put_u8(0);
+        }
+    }
+    Ok(dst)
+}
+
+/// Write frames with Consistent Overhead Byte Stuffing, for a peer using a [`CobsDecoder`]. Each
+/// frame is followed by a zero byte delimiter.
+#[derive(Default)]
+pub struct CobsEncoder {}
+
+impl CobsEncoder {
+    /// The most bytes [`CobsEncoder::encode`] will write for this payload, including the delimiter
+    pub fn encoded_len(&self, item: &[u8]) -> usize {
+        item.len() + item.len().saturating_sub(1) / 254 + 2
+    }
+
+    /// Write the stuffed payload followed by the delimiter
+    pub fn encode(&self, item: &[u8], dst: &mut BytesMut) {
+        dst.reserve(self.encoded_len(item));
+        let mut head = dst.len();
+        let mut code = 1u8;
+        dst.put_u8(0);
+        for (i, b) in item.iter().enumerate() {
+            if *b != 0 {
+                dst.put_u8(*b);
+                code += 1;
+            }
+            // NOTE a full block is only closed when more input follows, otherwise the trailing
+            // block would be an empty 0x01
+            if *b == 0 || (code == 0xFF && i + 1 < item.len()) {
+                dst[head] = code;
+                head = dst.len();
+                code = 1;
+                dst.put_u8(0);
+            }
+        }
+        dst[head] = code;
+        dst.put_u8(0);
+    }
+}
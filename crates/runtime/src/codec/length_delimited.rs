@@ -0,0 +1,173 @@
+//! length_delimited
+
+use super::Decode;
+use bytes::{Buf, BufMut, BytesMut};
+use std::{error, fmt};
+
+/// The width of the length prefix in front of each frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LengthWidth {
+    U8,
+    U16,
+    U32,
+}
+
+impl LengthWidth {
+    /// The number of bytes occupied by the length prefix
+    pub fn bytes(&self) -> usize {
+        match self {
+            LengthWidth::U8 => 1,
+            LengthWidth::U16 => 2,
+            LengthWidth::U32 => 4,
+        }
+    }
+
+    /// The largest payload the length prefix can describe
+    pub fn max(&self) -> usize {
+        match self {
+            LengthWidth::U8 => u8::MAX as usize,
+            LengthWidth::U16 => u16::MAX as usize,
+            LengthWidth::U32 => u32::MAX as usize,
+        }
+    }
+}
+
+/// The byte order of the length prefix
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// Describes the length prefix shared by [`LengthDelimitedDecoder`] and
+/// [`LengthDelimitedEncoder`]. The prefix holds the length of the payload only, not including the
+/// prefix itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LengthDelimited {
+    pub width: LengthWidth,
+    pub endian: Endian,
+    /// Reject frames with a payload larger than this many bytes, instead of buffering them
+    pub max_frame: Option<usize>,
+}
+
+impl Default for LengthDelimited {
+    /// A 2 byte big endian length prefix without a max frame guard
+    fn default() -> Self {
+        Self {
+            width: LengthWidth::U16,
+            endian: Endian::Big,
+            max_frame: None,
+        }
+    }
+}
+
+impl LengthDelimited {
+    fn check(&self, len: usize) -> Result<(), LengthDelimitedError> {
+        let max = self.max_frame.unwrap_or(usize::MAX).min(self.width.max());
+        match len > max {
+            true => Err(LengthDelimitedError::FrameTooLarge { len, max }),
+            false => Ok(()),
+        }
+    }
+
+    fn get_len(&self, src: &[u8]) -> usize {
+        let mut src = src;
+        match (self.width, self.endian) {
+            (LengthWidth::U8, _) => src.get_u8() as usize,
+            (LengthWidth::U16, Endian::Big) => src.get_u16() as usize,
+            (LengthWidth::U16, Endian::Little) => src.get_u16_le() as usize,
+            (LengthWidth::U32, Endian::Big) => src.get_u32() as usize,
+            (LengthWidth::U32, Endian::Little) => src.get_u32_le() as usize,
+        }
+    }
+
+    fn put_len(&self, len: usize, dst: &mut BytesMut) {
+        match (self.width, self.endian) {
+            (LengthWidth::U8, _) => dst.put_u8(len as u8),
+            (LengthWidth::U16, Endian::Big) => dst.put_u16(len as u16),
+            (LengthWidth::U16, Endian::Little) => dst.put_u16_le(len as u16),
+            (LengthWidth::U32, Endian::Big) => dst.put_u32(len as u32),
+            (LengthWidth::U32, Endian::Little) => dst.put_u32_le(len as u32),
+        }
+    }
+}
+
+/// A length delimited frame was larger than allowed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LengthDelimitedError {
+    /// The frame payload is larger than the max frame, or larger than the prefix can describe
+    FrameTooLarge { len: usize, max: usize },
+}
+
+impl fmt::Display for LengthDelimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LengthDelimitedError::FrameTooLarge { len, max } => {
+                write!(f, "frame too large => {len} > {max}")
+            }
+        }
+    }
+}
+
+impl error::Error for LengthDelimitedError {}
+
+/// A [`Decode`] implementation that splits up data into frames with a length prefix. The decoded
+/// frames do not include the prefix.
+///
+/// NOTE when a frame is rejected by the max frame guard, the decoder does not know where the next
+/// frame begins. The stream must be resynchronized by the caller.
+#[derive(Default)]
+pub struct LengthDelimitedDecoder {
+    config: LengthDelimited,
+}
+
+impl LengthDelimitedDecoder {
+    pub fn new(config: LengthDelimited) -> Self {
+        Self { config }
+    }
+}
+
+impl Decode for LengthDelimitedDecoder {
+    type Item = BytesMut;
+    type Error = LengthDelimitedError;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let head = self.config.width.bytes();
+        if src.len() < head {
+            return Ok(None);
+        }
+        let len = self.config.get_len(&src[..head]);
+        self.config.check(len)?;
+        if src.len() < head + len {
+            src.reserve(head + len - src.len());
+            return Ok(None);
+        }
+        src.advance(head);
+        Ok(Some(src.split_to(len)))
+    }
+}
+
+/// Write frames with a length prefix, for a peer using a [`LengthDelimitedDecoder`]
+#[derive(Default)]
+pub struct LengthDelimitedEncoder {
+    config: LengthDelimited,
+}
+
+impl LengthDelimitedEncoder {
+    pub fn new(config: LengthDelimited) -> Self {
+        Self { config }
+    }
+
+    /// The number of bytes [`LengthDelimitedEncoder::encode`] will write for this payload
+    pub fn encoded_len(&self, item: &[u8]) -> usize {
+        self.config.width.bytes() + item.len()
+    }
+
+    /// Write the length prefix followed by the payload
+    pub fn encode(&self, item: &[u8], dst: &mut BytesMut) -> Result<(), LengthDelimitedError> {
+        self.config.check(item.len())?;
+        dst.reserve(self.encoded_len(item));
+        self.config.put_len(item.len(), dst);
+        dst.put_slice(item);
+        Ok(())
+    }
+}
@@ -1,40 +1,136 @@
 //! lines
 
 use super::Decode;
-use bytes::BytesMut;
-use std::str;
+use bytes::{Buf, BytesMut};
+use std::{error, fmt, str};
 
-/// A simple [`Decoder`] and [`Encoder`] implementation that splits up data into lines. This uses
-/// the `\n` character as the line ending on all platforms.
+/// A line could not be decoded
+#[derive(Debug, PartialEq)]
+pub enum LinesError {
+    /// The line is not valid utf8
+    Utf8(str::Utf8Error),
+    /// The line is longer than the max length. The line is discarded up to the next delimiter
+    MaxLength,
+}
+
+impl fmt::Display for LinesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinesError::Utf8(e) => write!(f, "line utf8 error => {e}"),
+            LinesError::MaxLength => write!(f, "line max length exceeded"),
+        }
+    }
+}
+
+impl error::Error for LinesError {}
+
+/// A simple [`Decode`] implementation that splits up data into lines. By default this uses the
+/// `\n` character as the line ending on all platforms. A trailing `\r` is removed from each line,
+/// and empty lines are skipped.
 ///
 /// NOTE this implementation (much like much of this module) is lifted heavily from
-/// tokio_util::codec. However, unlike LinesCodec, we do not need to worry about max length because
-/// the Stream types will handle overflows
+/// tokio_util::codec. The max length is unbounded by default, because the Stream types will
+/// handle overflows. Devices that may send garbage without a delimiter should set a max length
+/// with [`LinesDecoder::with_max_len`].
 pub struct LinesDecoder {
     // Current index into the buffer so we avoid re-scanning the buffer each call to decode
     index: usize,
+    delimiter: Vec<u8>,
+    max_len: Option<usize>,
+    // True when a line exceeded the max length and we are dropping bytes until the next delimiter
+    discarding: bool,
 }
 
 impl Default for LinesDecoder {
     fn default() -> Self {
-        Self { index: 0 }
+        Self::new(b"\n")
+    }
+}
+
+impl LinesDecoder {
+    /// Split lines on the delimiter, such as `b"\r"` or `b"\r\n"`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is empty
+    pub fn new(delimiter: impl Into<Vec<u8>>) -> Self {
+        let delimiter = delimiter.into();
+        assert!(!delimiter.is_empty(), "line delimiter must not be empty");
+        Self {
+            index: 0,
+            delimiter,
+            max_len: None,
+            discarding: false,
+        }
+    }
+
+    /// Return [`LinesError::MaxLength`] for lines longer than max_len bytes, not including the
+    /// delimiter. The decoder then discards bytes up to the next delimiter so the stream can
+    /// recover.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    fn find(&self, src: &[u8]) -> Option<usize> {
+        let start = self.index.saturating_sub(self.delimiter.len() - 1);
+        src[start..]
+            .windows(self.delimiter.len())
+            .position(|window| window == self.delimiter)
+            .map(|offset| start + offset)
+    }
+
+    // Drop everything except bytes which may be the start of a delimiter
+    fn discard(&mut self, src: &mut BytesMut) {
+        src.advance(src.len().saturating_sub(self.delimiter.len() - 1));
+        self.index = src.len();
+        self.discarding = true;
     }
 }
 
 impl Decode for LinesDecoder {
     type Item = String;
-    type Error = str::Utf8Error;
+    type Error = LinesError;
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if let Some(offset) = src[self.index..src.len()].iter().position(|b| *b == b'\n') {
-            let line = src.split_to(self.index + offset + 1);
-            if line.len() > 2 {
-                str::from_utf8(&line[..line.len() - 2]).map(|s| Some(s.to_string()))
-            } else {
-                Ok(None)
+        loop {
+            match (self.find(src), self.discarding) {
+                (Some(offset), true) => {
+                    src.advance(offset + self.delimiter.len());
+                    self.index = 0;
+                    self.discarding = false;
+                }
+                (None, true) => {
+                    self.discard(src);
+                    return Ok(None);
+                }
+                (Some(offset), false) => {
+                    self.index = 0;
+                    let line = src.split_to(offset + self.delimiter.len());
+                    let line = match &line[..offset] {
+                        [line @ .., b'\r'] => line,
+                        line => line,
+                    };
+                    match self.max_len {
+                        Some(max) if line.len() > max => return Err(LinesError::MaxLength),
+                        _ if line.is_empty() => continue,
+                        _ => {
+                            return str::from_utf8(line)
+                                .map(|s| Some(s.to_string()))
+                                .map_err(LinesError::Utf8)
+                        }
+                    }
+                }
+                (None, false) => match self.max_len {
+                    Some(max) if src.len() > max + self.delimiter.len() => {
+                        self.discard(src);
+                        return Err(LinesError::MaxLength);
+                    }
+                    _ => {
+                        self.index = src.len();
+                        return Ok(None);
+                    }
+                },
             }
-        } else {
-            self.index = src.len();
-            Ok(None)
         }
     }
 }
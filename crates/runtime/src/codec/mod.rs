@@ -1,7 +1,11 @@
 //! codec
 
+pub mod cobs;
+pub mod length_delimited;
 pub mod lines;
 
+use std::{error, fmt};
+
 /// I/O completions will try and decode the incoming bytes and yeild some Items
 pub trait Decode {
     type Item;
@@ -25,6 +29,87 @@ pub trait Decode {
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error>;
 }
 
+/// Combinators to reuse a [`Decode`] implementation with a different item type
+pub trait DecodeExt: Decode + Sized {
+    /// Transform each decoded item
+    fn map<F, T>(self, f: F) -> Map<Self, F>
+    where
+        F: FnMut(Self::Item) -> T,
+    {
+        Map { inner: self, f }
+    }
+
+    /// Transform each decoded item with a mapping that may fail. The resulting decoder yields a
+    /// [`TryMapError`] of either the inner decode error or the mapping error
+    fn try_map<F, T, E>(self, f: F) -> TryMap<Self, F>
+    where
+        F: FnMut(Self::Item) -> Result<T, E>,
+        E: error::Error,
+    {
+        TryMap { inner: self, f }
+    }
+}
+
+impl<D: Decode> DecodeExt for D {}
+
+/// A decoder returned from [`DecodeExt::map`]
+pub struct Map<D, F> {
+    inner: D,
+    f: F,
+}
+
+impl<D, F, T> Decode for Map<D, F>
+where
+    D: Decode,
+    F: FnMut(D::Item) -> T,
+{
+    type Item = T;
+    type Error = D::Error;
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(self.inner.decode(src)?.map(&mut self.f))
+    }
+}
+
+/// A decoder returned from [`DecodeExt::try_map`]
+pub struct TryMap<D, F> {
+    inner: D,
+    f: F,
+}
+
+impl<D, F, T, E> Decode for TryMap<D, F>
+where
+    D: Decode,
+    F: FnMut(D::Item) -> Result<T, E>,
+    E: error::Error,
+{
+    type Item = T;
+    type Error = TryMapError<D::Error, E>;
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.inner.decode(src).map_err(TryMapError::Decode)? {
+            Some(item) => (self.f)(item).map(Some).map_err(TryMapError::Map),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Either the inner decoder failed or the mapping failed
+#[derive(Debug)]
+pub enum TryMapError<D, M> {
+    Decode(D),
+    Map(M),
+}
+
+impl<D: fmt::Display, M: fmt::Display> fmt::Display for TryMapError<D, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryMapError::Decode(e) => write!(f, "decode error => {e}"),
+            TryMapError::Map(e) => write!(f, "map error => {e}"),
+        }
+    }
+}
+
+impl<D: error::Error, M: error::Error> error::Error for TryMapError<D, M> {}
+
 /// Something that can be encoded into an array of bytes
 pub trait Encode {
     type Error: std::error::Error;
@@ -1,6 +1,10 @@
 //! cleanup
+use super::WaitPending;
 use std::{
+    any::Any,
+    cell::RefCell,
     ffi::c_void,
+    mem::ManuallyDrop,
     os::windows::prelude::{AsRawHandle, RawHandle},
 };
 use windows_sys::Win32::{Foundation::TRUE, System::Threading::*};
@@ -20,9 +24,22 @@ use windows_sys::Win32::{Foundation::TRUE, System::Threading::*};
 ///         use handle.into_raw() to mem forget and allow Cleanup group to close
 ///         the handle.
 ///
+/// Ordering: Closing a member individually removes it from the group. The pools in this crate
+/// (IE: [`crate::timer::TimerPool`], [`crate::work::WorkOncePool`]) close their handle when they
+/// drop, so drop the pools before the group and the group has nothing left to close. Dropping the
+/// group first closes the members, and the pools will close them a second time when they drop.
+/// To tear the pools down with the group instead, hand each pool to the group with
+/// `into_group_member` (IE: [`crate::timer::TimerPool::into_group_member`]). The group then
+/// closes the handle and keeps the context of the pool alive until the callbacks have returned.
+///
 /// [See also]
 /// (https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-createthreadpoolcleanupgroup)
-pub struct ThreadpoolCleanupGroup(PTP_CLEANUP_GROUP, *mut c_void);
+pub struct ThreadpoolCleanupGroup(
+    PTP_CLEANUP_GROUP,
+    *mut c_void,
+    /// The contexts of the members handed to the group, freed after the members are closed
+    RefCell<Vec<Box<dyn Any>>>,
+);
 
 impl ThreadpoolCleanupGroup {
     /// Create a threadpool cleanup group
@@ -30,16 +47,40 @@ impl ThreadpoolCleanupGroup {
     /// [See Also]
     /// (https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-seteventwhencallbackreturns)
     pub fn new() -> Self {
-        unsafe { Self(CreateThreadpoolCleanupGroup(), std::ptr::null_mut()) }
+        let group = unsafe { CreateThreadpoolCleanupGroup() };
+        Self(group, std::ptr::null_mut(), RefCell::new(Vec::new()))
     }
 
     pub fn with_context(mut self, ctx: *mut c_void) -> Self {
         self.1 = ctx;
         self
     }
+
+    /// Close every member of the group and then the group itself. Callbacks that have not started
+    /// are cancelled with [`WaitPending::Cancel`]. Blocks until running callbacks have returned.
+    ///
+    /// NOTE pools which were not handed to the group must be dropped first. See the ordering
+    /// notes on [`ThreadpoolCleanupGroup`]
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-closethreadpoolcleanupgroupmembers)
+    pub fn close_members(self, pending: WaitPending) {
+        let group = ManuallyDrop::new(self);
+        unsafe { CloseThreadpoolCleanupGroupMembers(group.0, pending as _, group.1) };
+        unsafe { CloseThreadpoolCleanupGroup(group.0) };
+        // The callbacks have returned, so the contexts of the members may be freed
+        drop(group.2.take());
+    }
+
+    /// Keep the context of a member alive until the members of the group are closed. The member
+    /// must no longer close its own handle
+    pub(crate) fn hold<C: 'static>(&self, context: C) {
+        self.2.borrow_mut().push(Box::new(context));
+    }
 }
 
 impl Drop for ThreadpoolCleanupGroup {
+    /// NOTE the contexts of the members are freed after the members are closed
     fn drop(&mut self) {
         unsafe { CloseThreadpoolCleanupGroupMembers(self.0, TRUE, self.1) };
         unsafe { CloseThreadpoolCleanupGroup(self.0) };
@@ -1,4 +1,5 @@
 //! environment
+use super::ThreadpoolCleanupGroup;
 use std::{
     io::{Error, Result},
     mem,
@@ -41,7 +42,10 @@ impl ThreadpoolHandle {
         }
     }
 
-    /// Set the minimum and maximum number of threads
+    /// Set the minimum number of threads. Windows retires threads which have been idle for a
+    /// while until the pool is back down to the minimum, so lower the minimum to let idle threads
+    /// be reclaimed (IE: after a burst of work). Threads which are running callbacks are not
+    /// affected
     ///
     /// [See also]
     /// (https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpoolthreadminimum)
@@ -63,6 +67,13 @@ impl ThreadpoolHandle {
         self
     }
 
+    /// Pin the pool to exactly `threads` threads, IE: for callbacks which must start with low
+    /// latency. Sets both the minimum and the maximum, so the threads are created immediately and
+    /// are never retired
+    pub fn persistent(&self, threads: u32) -> Result<&Self> {
+        self.max_threads(threads).min_threads(threads)
+    }
+
     /// Helper function to create a new thread pool environment associated with this threadpool
     pub fn new_environment(&self) -> ThreadpoolCallbackEnvironment {
         ThreadpoolCallbackEnvironment::new().with_pool(self.as_raw_handle() as _)
@@ -193,6 +204,13 @@ impl ThreadpoolCallbackEnvironment {
         self.0.CleanupGroupCancelCallback = cancel_callback;
         self
     }
+
+    /// Associates a [`ThreadpoolCleanupGroup`] with the callback environment. Every pool created
+    /// with this environment becomes a member of the group. See the ordering notes on
+    /// [`ThreadpoolCleanupGroup`]
+    pub fn with_group(self, group: &ThreadpoolCleanupGroup) -> Self {
+        self.with_cleanup_group(group.as_raw_handle() as _, None)
+    }
 }
 
 impl Drop for ThreadpoolCallbackEnvironment {
@@ -74,6 +74,25 @@ impl ThreadpoolCallbackInstance {
         unsafe { FreeLibraryWhenCallbackReturns(self.0, handle) };
         self
     }
+
+    /// Tell the threadpool this callback is finished with its threadpool object, even though the
+    /// callback has not returned yet. Functions which wait for outstanding callbacks (IE:
+    /// `WaitForThreadpoolWorkCallbacks`, or dropping a pool which closes the object) treat the
+    /// callback as complete.
+    ///
+    /// Without this, a callback which waits for or closes its own threadpool object (or waits on
+    /// a thread which is doing so) deadlocks against itself, because the wait can not finish until
+    /// the callback returns.
+    ///
+    /// NOTE the callback must not touch the threadpool object or its context afterwards, because
+    /// the object may be closed while the callback is still running
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-disassociatecurrentthreadfromcallback)
+    pub fn disassociate_current_thread(&self) -> &Self {
+        unsafe { DisassociateCurrentThreadFromCallback(self.0) };
+        self
+    }
 }
 
 impl AsRawHandle for ThreadpoolCallbackInstance {
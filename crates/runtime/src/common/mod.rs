@@ -5,9 +5,24 @@ pub mod environment;
 pub mod instance;
 
 pub use cleanup::ThreadpoolCleanupGroup;
-pub use environment::{ThreadpoolHandle, ThreadpoolCallbackEnvironment, ThreadpoolPriority};
+pub use environment::{ThreadpoolCallbackEnvironment, ThreadpoolHandle, ThreadpoolPriority};
 pub use instance::ThreadpoolCallbackInstance;
 
+/// Run the body of an `extern "system"` callback and abort the process if the body panics.
+/// Unwinding across the callback boundary into windows is undefined behavior
+pub fn abort_on_unwind<F, R>(callback: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::error!(callback, "callback panicked, aborting");
+            std::process::abort()
+        }
+    }
+}
+
 /// Wait for pending threadpool callbacks, or cancel pending threadpool callbacks
 #[repr(u32)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -1,8 +1,20 @@
 //! event.rs
 
+use crate::wait::{WaitFuture, WaitPool};
+use bitflags::bitflags;
 use windows_sys::Win32::{
-    Foundation::{FALSE, HANDLE, TRUE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT},
-    System::Threading::{CreateEventW, ResetEvent, SetEvent, WaitForSingleObject, INFINITE},
+    Foundation::{
+        FALSE, HANDLE, TRUE, WAIT_ABANDONED, WAIT_ABANDONED_0, WAIT_FAILED, WAIT_OBJECT_0,
+        WAIT_TIMEOUT,
+    },
+    System::{
+        SystemServices::MAXIMUM_WAIT_OBJECTS,
+        Threading::{
+            CreateEventW, OpenEventW, ResetEvent, SetEvent, WaitForMultipleObjects,
+            WaitForSingleObject, EVENT_ALL_ACCESS, EVENT_MODIFY_STATE, INFINITE,
+            SYNCHRONIZATION_SYNCHRONIZE,
+        },
+    },
 };
 
 use std::{
@@ -36,6 +48,51 @@ where
     OwnedEventHandle::named(name, reset, state)
 }
 
+/// See [`OwnedEventHandle::open`]
+pub fn open<O>(name: O, access: EventAccess) -> io::Result<OwnedEventHandle>
+where
+    O: Into<OsString>,
+{
+    OwnedEventHandle::open(name, access)
+}
+
+/// Block until any of the objects is signaled, with optional timeout, and return the index of the
+/// signaled object. When more than one object is signaled the lowest index is returned. IE: wait
+/// on an event plus a "cancel" event which another thread sets to break out of the wait early.
+///
+/// At most [`MAXIMUM_WAIT_OBJECTS`] objects may be waited on. See [`WaitPool`] to wait
+/// asynchronously instead. When a mutex is abandoned, the index of the mutex is returned with
+/// [`EventError::AbandonedAt`]
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-waitformultipleobjects)
+pub fn wait_any(
+    objects: &[&dyn Waitable],
+    duration: Option<Duration>,
+) -> Result<usize, EventError> {
+    if objects.is_empty() || objects.len() > MAXIMUM_WAIT_OBJECTS as usize {
+        let msg = format!("wait_any expects between 1 and {MAXIMUM_WAIT_OBJECTS} objects");
+        return Err(EventError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            msg,
+        )));
+    }
+    let handles = objects
+        .iter()
+        .map(|object| object.as_raw_handle() as HANDLE)
+        .collect::<Vec<_>>();
+    let count = handles.len() as u32;
+    let dur: u32 = duration.map(|d| d.as_millis() as _).unwrap_or(INFINITE);
+    match unsafe { WaitForMultipleObjects(count, handles.as_ptr(), FALSE, dur) } {
+        n if (WAIT_OBJECT_0..WAIT_OBJECT_0 + count).contains(&n) => Ok((n - WAIT_OBJECT_0) as _),
+        n if (WAIT_ABANDONED_0..WAIT_ABANDONED_0 + count).contains(&n) => {
+            Err(EventError::AbandonedAt((n - WAIT_ABANDONED_0) as _))
+        }
+        WAIT_FAILED => Err(EventError::Failed),
+        WAIT_TIMEOUT => Err(EventError::Timeout),
+        _ => Err(EventError::Io(io::Error::last_os_error())),
+    }
+}
+
 /// The Win32 Event API is impled internally for Shared and Borrowed Event handles
 /// See OwnedEventHandle::new for details
 pub trait Event {
@@ -55,11 +112,33 @@ pub trait Event {
     /// [see also]
     /// (https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-waitforsingleobject)
     fn wait(&self, duration: Option<std::time::Duration>) -> Result<(), EventError>;
+
+    /// Set and then reset the event. Threads which are already waiting on a manual reset event
+    /// are released, and the event is left nonsignaled. An auto reset event releases at most one
+    /// waiting thread.
+    ///
+    /// NOTE this is not the deprecated PulseEvent. A thread which is not waiting at the moment
+    /// the event is set will miss the pulse
+    fn pulse(&self) -> std::io::Result<()> {
+        self.set()?;
+        self.reset()
+    }
+}
+
+/// A kernel object that can be waited on asynchronously with a [`WaitPool`]. Saves casting the
+/// raw handle when starting a wait.
+pub trait Waitable: AsRawHandle {
+    /// Start waiting on the threadpool. See [`WaitPool::start`]
+    fn wait_async(&self, pool: &mut WaitPool, timeout: Option<Duration>) -> WaitFuture {
+        pool.start(self.as_raw_handle() as _, timeout)
+    }
 }
 
 #[derive(Debug)]
 pub enum EventError {
     Abandoned,
+    /// The object at this index of a [`wait_any`] is an abandoned mutex
+    AbandonedAt(usize),
     Failed,
     Timeout,
     Io(io::Error),
@@ -69,6 +148,7 @@ impl fmt::Display for EventError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             EventError::Abandoned => write!(f, "Event abandoned"),
+            EventError::AbandonedAt(n) => write!(f, "Event abandoned at index {n}"),
             EventError::Failed => write!(f, "Event failed"),
             EventError::Timeout => write!(f, "Event timeout"),
             EventError::Io(e) => write!(f, "Event io error => {e}"),
@@ -86,7 +166,7 @@ impl error::Error for EventError {}
 /// event object, and the system automatically resets the event state to
 /// nonsignaled after a single waiting thread has been released.
 #[repr(i32)]
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventReset {
     Manual = TRUE,
     Automatic = FALSE,
@@ -102,8 +182,29 @@ pub enum EventInitialState {
     Unset = FALSE,
 }
 
+bitflags! {
+    /// The access rights requested when opening an existing event with [`OwnedEventHandle::open`]
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/sync/synchronization-object-security-and-access-rights)
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct EventAccess: u32 {
+        /// Required to set or reset the event
+        const MODIFY_STATE = EVENT_MODIFY_STATE;
+        /// Required to wait on the event
+        const SYNCHRONIZE = SYNCHRONIZATION_SYNCHRONIZE;
+        /// All possible access rights for an event
+        const ALL_ACCESS = EVENT_ALL_ACCESS;
+    }
+}
+
 /// Like [`OwnedHandle`] except extended with Event api
-pub struct OwnedEventHandle(OwnedHandle);
+pub struct OwnedEventHandle {
+    handle: OwnedHandle,
+    /// Windows has no getter for the reset mode so we remember the mode used at creation. An
+    /// event created from a raw handle has an unknown reset mode
+    reset: Option<EventReset>,
+}
 
 impl OwnedEventHandle {
     /// Create a system event
@@ -125,6 +226,30 @@ impl OwnedEventHandle {
         Self::new_raw(kernel_name.as_ptr() as _, reset, state)
     }
 
+    /// Open an existing named event, IE: an event created by another process. The reset mode of
+    /// an opened event is unknown
+    ///
+    /// [OpenEventW](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-openeventw)
+    pub fn open<O>(name: O, access: EventAccess) -> io::Result<OwnedEventHandle>
+    where
+        O: Into<OsString>,
+    {
+        let kernel_name = name
+            .into()
+            .encode_wide()
+            .chain(Some(0).into_iter())
+            .collect::<Vec<_>>();
+        unsafe {
+            let raw = OpenEventW(access.bits(), FALSE, kernel_name.as_ptr());
+            let handle = HandleOrNull::from_raw_handle(raw as _);
+            OwnedHandle::try_from(handle).map_err(|_| io::Error::last_os_error())
+        }
+        .map(|handle| Self {
+            handle,
+            reset: None,
+        })
+    }
+
     /// Create a system event with out a name
     ///
     /// [CreateEventW](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-createeventa)
@@ -142,11 +267,20 @@ impl OwnedEventHandle {
             let handle = HandleOrNull::from_raw_handle(raw as _);
             OwnedHandle::try_from(handle).map_err(|_| io::Error::last_os_error())
         }
-        .map(Self)
+        .map(|handle| Self {
+            handle,
+            reset: Some(reset),
+        })
+    }
+
+    /// The reset mode passed when the event was created. Returns None when the event was created
+    /// from a raw handle
+    pub fn reset_mode(&self) -> Option<EventReset> {
+        self.reset
     }
 
     pub fn as_handle(&self) -> BorrowedEventHandle<'_> {
-        BorrowedEventHandle(self.0.as_handle())
+        BorrowedEventHandle(self.handle.as_handle())
     }
 
     pub fn borrow_raw(&self) -> RawEventHandle {
@@ -156,13 +290,16 @@ impl OwnedEventHandle {
 
 impl AsRawHandle for OwnedEventHandle {
     fn as_raw_handle(&self) -> RawHandle {
-        self.0.as_raw_handle()
+        self.handle.as_raw_handle()
     }
 }
 
 impl FromRawHandle for OwnedEventHandle {
     unsafe fn from_raw_handle(handle: RawHandle) -> Self {
-        Self(OwnedHandle::from_raw_handle(handle))
+        Self {
+            handle: OwnedHandle::from_raw_handle(handle),
+            reset: None,
+        }
     }
 }
 
@@ -205,6 +342,8 @@ macro_rules! impl_event {
                 self::wait(self.as_raw_handle() as _, duration)
             }
         }
+
+        impl Waitable for $handle {}
     };
 }
 
@@ -229,7 +368,7 @@ fn reset(handle: HANDLE) -> io::Result<()> {
 }
 
 #[inline(always)]
-fn wait(handle: HANDLE, duration: Option<Duration>) -> Result<(), EventError> {
+pub(crate) fn wait(handle: HANDLE, duration: Option<Duration>) -> Result<(), EventError> {
     let dur: u32 = duration.map(|d| d.as_millis() as _).unwrap_or(INFINITE);
     match unsafe { WaitForSingleObject(handle, dur as _) } {
         WAIT_OBJECT_0 => Ok(()),
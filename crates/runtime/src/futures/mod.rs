@@ -2,8 +2,10 @@
 
 use futures::Stream;
 use std::future::Future;
+mod until_signal;
 mod watch;
 
+pub use until_signal::UntilSignal;
 pub use watch::{Signal, Watch};
 
 impl<T: ?Sized> FuturesExt for T where T: Future {}
@@ -17,6 +19,15 @@ pub trait FuturesExt: Future {
     {
         Watch::future(self)
     }
+
+    /// Like [`FuturesExt::watch`] except an existing [`Signal`] is reset and re-armed instead of
+    /// creating a new one
+    fn watch_with(self, signal: &Signal) -> Watch<Self>
+    where
+        Self: Sized,
+    {
+        Watch::future_with(self, signal)
+    }
 }
 
 pub trait StreamExt: Stream {
@@ -26,4 +37,24 @@ pub trait StreamExt: Stream {
     {
         Watch::stream(self)
     }
+
+    /// Like [`StreamExt::watch`] except an existing [`Signal`] is reset and re-armed instead of
+    /// creating a new one
+    fn watch_with(self, signal: &Signal) -> Watch<Self>
+    where
+        Self: Sized,
+    {
+        Watch::stream_with(self, signal)
+    }
+
+    /// End the stream when `until` resolves, IE: stop a read stream when a [`Signal`] fires or the
+    /// device is unplugged. Items which are ready when `until` resolves are dropped with the
+    /// stream
+    fn until_signal<F>(self, until: F) -> UntilSignal<Self, F>
+    where
+        Self: Sized,
+        F: Future,
+    {
+        UntilSignal::new(self, until)
+    }
 }
@@ -0,0 +1,68 @@
+//! until_signal
+use futures::Stream;
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    #[project = UntilSignalProj]
+    #[project_replace = UntilSignalProjReplace]
+    #[derive(Debug)]
+    #[must_use = "streams do nothing unless polled"]
+    pub enum UntilSignal<St, F> {
+        Incomplete {
+            #[pin]
+            inner: St,
+            #[pin]
+            until: F,
+        },
+        Complete,
+    }
+}
+
+impl<St, F> UntilSignal<St, F>
+where
+    St: Stream,
+    F: Future,
+{
+    pub(in crate::futures) fn new(inner: St, until: F) -> UntilSignal<St, F> {
+        UntilSignal::Incomplete { inner, until }
+    }
+}
+
+impl<St, F> UntilSignal<St, F> {
+    /// Return a reference to the stream if the stream has not ended yet
+    pub fn inner(&self) -> Option<&St> {
+        match self {
+            UntilSignal::Incomplete { inner, .. } => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl<St, F> Stream for UntilSignal<St, F>
+where
+    St: Stream,
+    F: Future,
+{
+    type Item = St::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.as_mut().project() {
+            UntilSignalProj::Incomplete { inner, until } => {
+                // The signal wins when both are ready so that no more items are read after it
+                let next = match until.poll(cx) {
+                    Poll::Ready(_) => Poll::Ready(None),
+                    Poll::Pending => inner.poll_next(cx),
+                };
+                if let Poll::Ready(None) = next {
+                    self.project_replace(UntilSignal::Complete);
+                }
+                next
+            }
+            UntilSignalProj::Complete => Poll::Ready(None),
+        }
+    }
+}
@@ -12,21 +12,102 @@ use std::{
 #[derive(Debug, Default)]
 pub struct Inner {
     signal: bool,
+    /// The waker of the first [`Signal`]. Kept apart so a single waiter does not allocate
     waker: Option<Waker>,
+    /// The wakers of every [`Signal::subscribe`], indexed by the subscriber id minus one
+    subscribers: Vec<Option<Waker>>,
+    /// Indexes of [`Inner::subscribers`] freed by a dropped subscriber, reused by the next
+    /// subscriber so the slots are bounded by the most subscribers alive at once
+    vacant: Vec<usize>,
 }
 
 impl Inner {
     fn signal(&mut self) {
         self.signal = true;
-        if let Some(waker) = self.waker.as_ref() {
+        let subscribers = self.subscribers.iter().flatten();
+        for waker in self.waker.iter().chain(subscribers) {
             waker.wake_by_ref()
         }
     }
+
+    fn reset(&mut self) {
+        self.signal = false;
+    }
+
+    fn slot(&mut self, id: usize) -> &mut Option<Waker> {
+        match id {
+            0 => &mut self.waker,
+            n => &mut self.subscribers[n - 1],
+        }
+    }
+
+    /// Reserve a slot for a new subscriber and return the subscriber id
+    fn subscribe(&mut self) -> usize {
+        match self.vacant.pop() {
+            Some(index) => index + 1,
+            None => {
+                self.subscribers.push(None);
+                self.subscribers.len()
+            }
+        }
+    }
+
+    /// Free the slot of a dropped subscriber
+    fn unsubscribe(&mut self, id: usize) {
+        self.slot(id).take();
+        if id > 0 {
+            self.vacant.push(id - 1);
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct Signal {
     shared: Arc<Mutex<Inner>>,
+    id: usize,
+}
+
+impl Signal {
+    /// Returns true if the watched future or stream has completed
+    pub fn is_signaled(&self) -> bool {
+        self.shared.lock().signal
+    }
+
+    /// Clear the signaled flag so that this signal may be re-armed by another [`Watch`]. See
+    /// [`super::FuturesExt::watch_with`] and [`super::StreamExt::watch_with`].
+    ///
+    /// NOTE if a previous [`Watch`] sharing this signal has not completed yet, it will still
+    /// signal when it completes. The flag is shared, so every subscriber is reset too
+    pub fn reset(&self) -> &Self {
+        self.shared.lock().reset();
+        self
+    }
+
+    /// Create another [`Signal`] for the same [`Watch`], IE: so that more than one task can wait
+    /// for a read to finish. Every subscriber resolves when the watched future or stream
+    /// completes
+    pub fn subscribe(&self) -> Signal {
+        let id = self.shared.lock().subscribe();
+        Signal {
+            shared: Arc::clone(&self.shared),
+            id,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Signal {
+    /// The number of subscriber slots, including vacant slots
+    pub(crate) fn subscriber_slots(&self) -> usize {
+        self.shared.lock().subscribers.len()
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        // Forget our waker so a completing watch does not wake a task which stopped waiting
+        self.shared.lock().unsubscribe(self.id);
+    }
 }
 
 impl Future for Signal {
@@ -40,7 +121,8 @@ impl Future for Signal {
             true => Poll::Ready(()),
             false => {
                 // Some waker accounting
-                shared.waker = match shared.waker.take() {
+                let slot = shared.slot(self.id);
+                *slot = match slot.take() {
                     Some(old_waker) => match old_waker.will_wake(new_waker) {
                         true => Some(old_waker),
                         false => Some(new_waker.clone()),
@@ -80,6 +162,14 @@ where
         };
         (signal, watch)
     }
+
+    pub(in crate::futures) fn future_with(inner: Fut, signal: &Signal) -> Watch<Fut> {
+        signal.reset();
+        Watch::Incomplete {
+            inner,
+            signal: Arc::clone(&signal.shared),
+        }
+    }
 }
 
 impl<St> Watch<St>
@@ -94,16 +184,34 @@ where
         };
         (signal, watch)
     }
+
+    pub(in crate::futures) fn stream_with(inner: St, signal: &Signal) -> Watch<St> {
+        signal.reset();
+        Watch::Incomplete {
+            inner,
+            signal: Arc::clone(&signal.shared),
+        }
+    }
 }
 
 impl<I> Watch<I> {
     /// Return a reference to the future if the future has not completed yet
     pub fn inner(&self) -> Option<&I> {
         match self {
-            Watch::Incomplete { inner, .. } => Some(&inner),
+            Watch::Incomplete { inner, .. } => Some(inner),
             _ => None,
         }
     }
+
+    /// Return a pinned mutable reference to the future if the future has not completed yet, IE:
+    /// to change the configuration of a stream while it is running. The inner future is dropped
+    /// when it completes, so callers must handle `None` rather than assume the inner exists
+    pub fn try_inner_mut(self: Pin<&mut Self>) -> Option<Pin<&mut I>> {
+        match self.project() {
+            WatchProj::Incomplete { inner, .. } => Some(inner),
+            WatchProj::Complete => None,
+        }
+    }
 }
 
 impl<F> Future for Watch<F>
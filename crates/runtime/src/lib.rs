@@ -7,6 +7,7 @@ pub mod codec;
 pub mod common;
 pub mod event;
 pub mod futures;
+pub mod sync;
 pub mod timer;
 pub mod usb;
 pub mod wait;
@@ -0,0 +1,104 @@
+//! sync
+
+use crate::event::{EventError, Waitable};
+use windows_sys::Win32::{
+    Foundation::FALSE,
+    System::Threading::{CreateSemaphoreW, ReleaseSemaphore},
+};
+
+use std::{
+    ffi::OsString,
+    io,
+    os::windows::{
+        io::{AsRawHandle, FromRawHandle, HandleOrNull, OwnedHandle, RawHandle},
+        prelude::*,
+    },
+    time::Duration,
+};
+
+/// See [`OwnedSemaphoreHandle::anonymous`]
+pub fn semaphore(initial: u32, max: u32) -> io::Result<OwnedSemaphoreHandle> {
+    OwnedSemaphoreHandle::anonymous(initial, max)
+}
+
+/// Like [`OwnedHandle`] except extended with Semaphore api. A wait on the semaphore takes a slot
+/// and [`OwnedSemaphoreHandle::release`] gives slots back, IE: to limit how many devices are
+/// opened at a time.
+///
+/// ```ignore
+/// let sem = sync::semaphore(4, 4)?;
+/// sem.wait_async(&mut pool, None).await?;
+/// // ... use the slot
+/// sem.release(1)?;
+/// ```
+pub struct OwnedSemaphoreHandle(OwnedHandle);
+
+impl OwnedSemaphoreHandle {
+    /// Create a system semaphore
+    ///
+    /// [CreateSemaphoreW](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-createsemaphorew)
+    pub fn named<O>(name: O, initial: u32, max: u32) -> io::Result<OwnedSemaphoreHandle>
+    where
+        O: Into<OsString>,
+    {
+        let kernel_name = name
+            .into()
+            .encode_wide()
+            .chain(Some(0).into_iter())
+            .collect::<Vec<_>>();
+        Self::new_raw(kernel_name.as_ptr() as _, initial, max)
+    }
+
+    /// Create a system semaphore with out a name
+    ///
+    /// [CreateSemaphoreW](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-createsemaphorew)
+    pub fn anonymous(initial: u32, max: u32) -> io::Result<OwnedSemaphoreHandle> {
+        Self::new_raw(std::ptr::null(), initial, max)
+    }
+
+    pub fn new_raw(name: *const u16, initial: u32, max: u32) -> io::Result<OwnedSemaphoreHandle> {
+        let initial = i32::try_from(initial).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let max = i32::try_from(max).map_err(|_| io::ErrorKind::InvalidInput)?;
+        unsafe {
+            let raw = CreateSemaphoreW(std::ptr::null(), initial, max, name);
+            let handle = HandleOrNull::from_raw_handle(raw as _);
+            OwnedHandle::try_from(handle).map_err(|_| io::Error::last_os_error())
+        }
+        .map(Self)
+    }
+
+    /// Give back `count` slots and return the count before the release. Fails if the release
+    /// would raise the count above the maximum, in which case the count is not changed
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-releasesemaphore)
+    pub fn release(&self, count: u32) -> io::Result<u32> {
+        let count = i32::try_from(count).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let mut previous = 0;
+        match unsafe { ReleaseSemaphore(self.as_raw_handle() as _, count, &mut previous) } {
+            FALSE => Err(io::Error::last_os_error()),
+            _ => Ok(previous as _),
+        }
+    }
+
+    /// Take a slot, blocking with optional timeout
+    ///
+    /// [see also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/synchapi/nf-synchapi-waitforsingleobject)
+    pub fn wait(&self, duration: Option<Duration>) -> Result<(), EventError> {
+        crate::event::wait(self.as_raw_handle() as _, duration)
+    }
+}
+
+impl Waitable for OwnedSemaphoreHandle {}
+
+impl AsRawHandle for OwnedSemaphoreHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+impl FromRawHandle for OwnedSemaphoreHandle {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        Self(OwnedHandle::from_raw_handle(handle))
+    }
+}
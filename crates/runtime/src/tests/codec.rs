@@ -0,0 +1,153 @@
+//! codec
+
+use crate::codec::{
+    cobs::{CobsDecoder, CobsEncoder, CobsError},
+    length_delimited::{
+        Endian, LengthDelimited, LengthDelimitedDecoder, LengthDelimitedEncoder,
+        LengthDelimitedError, LengthWidth,
+    },
+    lines::{LinesDecoder, LinesError},
+    Decode, DecodeExt, TryMapError,
+};
+use bytes::BytesMut;
+
+#[test]
+fn codec_length_delimited() {
+    let mut decoder = LengthDelimitedDecoder::default();
+    let mut src = BytesMut::from(&[0x00, 0x03, b'a', b'b'][..]);
+    assert_eq!(None, decoder.decode(&mut src).unwrap());
+    src.extend_from_slice(&[b'c', 0x00, 0x01, b'd', 0x00]);
+    assert_eq!(&b"abc"[..], &decoder.decode(&mut src).unwrap().unwrap()[..]);
+    assert_eq!(&b"d"[..], &decoder.decode(&mut src).unwrap().unwrap()[..]);
+    assert_eq!(None, decoder.decode(&mut src).unwrap());
+    assert_eq!(&[0x00][..], &src[..]);
+}
+
+#[test]
+fn codec_length_delimited_round_trip() {
+    let config = LengthDelimited {
+        width: LengthWidth::U32,
+        endian: Endian::Little,
+        max_frame: Some(4),
+    };
+    let encoder = LengthDelimitedEncoder::new(config);
+    let mut decoder = LengthDelimitedDecoder::new(config);
+    let mut dst = BytesMut::new();
+    encoder.encode(b"1234", &mut dst).unwrap();
+    assert_eq!(&[4, 0, 0, 0, b'1', b'2', b'3', b'4'][..], &dst[..]);
+    assert_eq!(
+        Err(LengthDelimitedError::FrameTooLarge { len: 5, max: 4 }),
+        encoder.encode(b"12345", &mut dst)
+    );
+    assert_eq!(
+        &b"1234"[..],
+        &decoder.decode(&mut dst).unwrap().unwrap()[..]
+    );
+    let mut src = BytesMut::from(&[5, 0, 0, 0][..]);
+    assert_eq!(
+        Err(LengthDelimitedError::FrameTooLarge { len: 5, max: 4 }),
+        decoder.decode(&mut src)
+    );
+}
+
+#[test]
+fn codec_cobs() {
+    let encoder = CobsEncoder::default();
+    let mut decoder = CobsDecoder::default();
+    let mut dst = BytesMut::new();
+    encoder.encode(&[0x11, 0x00, 0x00, 0x22], &mut dst);
+    assert_eq!(&[0x02, 0x11, 0x01, 0x02, 0x22, 0x00][..], &dst[..]);
+    let mut src = dst.split_to(3);
+    assert_eq!(None, decoder.decode(&mut src).unwrap());
+    src.unsplit(dst);
+    let frame = decoder.decode(&mut src).unwrap().unwrap();
+    assert_eq!(&[0x11, 0x00, 0x00, 0x22][..], &frame[..]);
+    assert!(src.is_empty());
+}
+
+#[test]
+fn codec_cobs_max_run() {
+    let encoder = CobsEncoder::default();
+    let mut decoder = CobsDecoder::default();
+    for len in [253, 254, 255, 508, 509] {
+        let item = (0..len).map(|i| (i % 255) as u8 + 1).collect::<Vec<u8>>();
+        let mut dst = BytesMut::new();
+        encoder.encode(&item, &mut dst);
+        assert_eq!(encoder.encoded_len(&item), dst.len());
+        assert_eq!(1, dst.iter().filter(|b| **b == 0).count());
+        assert_eq!(&item[..], &decoder.decode(&mut dst).unwrap().unwrap()[..]);
+    }
+    let mut src = BytesMut::from(&[0x05, 0x11, 0x00][..]);
+    assert_eq!(Err(CobsError::InvalidStuffing), decoder.decode(&mut src));
+}
+
+#[test]
+fn codec_cobs_block_boundary() {
+    let encoder = CobsEncoder::default();
+    let encode = |item: &[u8]| {
+        let mut dst = BytesMut::new();
+        encoder.encode(item, &mut dst);
+        dst.to_vec()
+    };
+
+    // 253 bytes fit in a single block
+    let item = (1..=253).collect::<Vec<u8>>();
+    let expect = [&[0xFE][..], &item, &[0x00]].concat();
+    assert_eq!(expect, encode(&item));
+
+    // 254 bytes fill a block exactly, and no empty block follows
+    let item = (1..=254).collect::<Vec<u8>>();
+    let expect = [&[0xFF][..], &item, &[0x00]].concat();
+    assert_eq!(expect, encode(&item));
+
+    // 255 bytes spill into a second block
+    let item = (1..=255).collect::<Vec<u8>>();
+    let expect = [&[0xFF][..], &item[..254], &[0x02, 0xFF, 0x00]].concat();
+    assert_eq!(expect, encode(&item));
+
+    // A zero after a full block is an empty block
+    let item = [&[0x01; 254][..], &[0x00]].concat();
+    let expect = [&[0xFF][..], &[0x01; 254], &[0x01, 0x01, 0x00]].concat();
+    assert_eq!(expect, encode(&item));
+    assert!(expect.len() <= encoder.encoded_len(&item));
+
+    // An empty payload is a single empty block
+    assert_eq!(vec![0x01, 0x00], encode(&[]));
+}
+
+#[test]
+fn codec_map() {
+    let mut decoder = LinesDecoder::default()
+        .map(|line| line.len())
+        .try_map(u8::try_from);
+    let mut src = BytesMut::from(&b"abc\r\n"[..]);
+    assert_eq!(3, decoder.decode(&mut src).unwrap().unwrap());
+    let mut src = BytesMut::from(&[b'a'; 300][..]);
+    src.extend_from_slice(b"\r\n");
+    assert!(matches!(decoder.decode(&mut src), Err(TryMapError::Map(_))));
+}
+
+#[test]
+fn codec_lines() {
+    let mut decoder = LinesDecoder::default();
+    let mut src = BytesMut::from(&b"ab\r\n\ncd"[..]);
+    assert_eq!("ab", decoder.decode(&mut src).unwrap().unwrap());
+    assert_eq!(None, decoder.decode(&mut src).unwrap());
+    src.extend_from_slice(b"\n");
+    assert_eq!("cd", decoder.decode(&mut src).unwrap().unwrap());
+    assert!(src.is_empty());
+}
+
+#[test]
+fn codec_lines_max_len() {
+    let mut decoder = LinesDecoder::new(b"\r").with_max_len(4);
+    let mut src = BytesMut::from(&b"abcd"[..]);
+    assert_eq!(None, decoder.decode(&mut src).unwrap());
+    src.extend_from_slice(b"efg");
+    assert_eq!(Err(LinesError::MaxLength), decoder.decode(&mut src));
+    src.extend_from_slice(b"ijk\rok\r");
+    assert_eq!("ok", decoder.decode(&mut src).unwrap().unwrap());
+    let mut src = BytesMut::from(&b"abcde\rok\r"[..]);
+    assert_eq!(Err(LinesError::MaxLength), decoder.decode(&mut src));
+    assert_eq!("ok", decoder.decode(&mut src).unwrap().unwrap());
+}
@@ -1,8 +1,8 @@
 use std::os::windows::io::AsRawHandle;
 
 use crate::{
-    event::{Event, EventInitialState, EventReset},
-    wait::{WaitError, WaitPool},
+    event::{Event, EventAccess, EventInitialState, EventReset, Waitable},
+    wait::{MultiWaitPool, WaitError, WaitPool},
 };
 use futures::FutureExt;
 
@@ -48,3 +48,164 @@ fn threadpool_test_event() {
     let poll = fut.poll_unpin(&mut cx);
     assert!(poll.is_ready());
 }
+
+#[test]
+fn threadpool_test_event_wait_async() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+    let ev = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let mut pool = WaitPool::new().unwrap();
+    let mut fut = ev.wait_async(&mut pool, None);
+    assert!(fut.poll_unpin(&mut cx).is_pending());
+    ev.set().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(fut.poll_unpin(&mut cx).is_ready());
+}
+
+#[test]
+fn threadpool_test_wait_timeout() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Wait on an event which is never set
+    let ev = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let mut pool = WaitPool::new().unwrap();
+    let mut fut = pool.start(
+        ev.as_raw_handle() as _,
+        Some(std::time::Duration::from_millis(100)),
+    );
+
+    // Make sure we are still pending well before the timeout
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert!(fut.poll_unpin(&mut cx).is_pending());
+
+    // Make sure we timeout shortly after the timeout
+    // NOTE we set the time delay to allow kernel some time to drive our future
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let poll = fut.poll_unpin(&mut cx);
+    assert_eq!(std::task::Poll::Ready(Err(WaitError::Timeout)), poll);
+}
+
+#[test]
+fn threadpool_test_event_reset_mode() {
+    let manual = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let auto = crate::event::anonymous(EventReset::Automatic, EventInitialState::Set).unwrap();
+    assert_eq!(Some(EventReset::Manual), manual.reset_mode());
+    assert_eq!(Some(EventReset::Automatic), auto.reset_mode());
+}
+
+#[test]
+fn threadpool_test_event_open() {
+    let name = "msft-runtime-test-event-open";
+    let ours = crate::event::named(name, EventReset::Manual, EventInitialState::Unset).unwrap();
+    let theirs = crate::event::open(name, EventAccess::MODIFY_STATE).unwrap();
+    assert_eq!(None, theirs.reset_mode());
+
+    // Setting the opened event signals the event we created
+    theirs.set().unwrap();
+    assert!(ours.wait(Some(std::time::Duration::ZERO)).is_ok());
+
+    // A pulse leaves the event nonsignaled
+    theirs.pulse().unwrap();
+    assert!(ours.wait(Some(std::time::Duration::ZERO)).is_err());
+
+    // Opening an event that does not exist fails
+    assert!(
+        crate::event::open("msft-runtime-test-event-missing", EventAccess::ALL_ACCESS).is_err()
+    );
+}
+
+#[test]
+fn threadpool_test_multi_wait() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Wait on two events with the same pool
+    let a = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let b = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let mut pool = MultiWaitPool::new();
+    let mut fut_a = pool.start(a.as_raw_handle() as _, None).unwrap();
+    let mut fut_b = pool.start(b.as_raw_handle() as _, None).unwrap();
+    assert_eq!(2, pool.len());
+    assert!(fut_a.poll_unpin(&mut cx).is_pending());
+    assert!(fut_b.poll_unpin(&mut cx).is_pending());
+
+    // Setting one event only resolves the future of that event
+    // NOTE we set the time delay to allow kernel some time to drive our future
+    b.set().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(fut_a.poll_unpin(&mut cx).is_pending());
+    assert!(fut_b.poll_unpin(&mut cx).is_ready());
+    assert_eq!(1, pool.len());
+
+    // Starting another wait closes the completed registration
+    b.reset().unwrap();
+    let mut fut_b = pool.start(b.as_raw_handle() as _, None).unwrap();
+    assert_eq!(2, pool.len());
+    assert_eq!(2, pool.prune().registrations());
+
+    // Cancel the remaining waits
+    pool.cancel_all();
+    let poll = fut_a.poll_unpin(&mut cx);
+    assert_eq!(std::task::Poll::Ready(Err(WaitError::Cancelled)), poll);
+    assert!(fut_b.poll_unpin(&mut cx).is_ready());
+    assert!(pool.is_empty());
+    assert_eq!(0, pool.prune().registrations());
+}
+
+#[test]
+fn threadpool_test_semaphore() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // One slot available, the first wait takes it
+    let sem = crate::sync::semaphore(1, 2).unwrap();
+    let mut pool = WaitPool::new().unwrap();
+    let mut fut = sem.wait_async(&mut pool, None);
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(fut.poll_unpin(&mut cx).is_ready());
+
+    // No slots left
+    let mut pool = WaitPool::new().unwrap();
+    let mut fut = sem.wait_async(&mut pool, None);
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(fut.poll_unpin(&mut cx).is_pending());
+
+    // Releasing returns the previous count and wakes the waiter
+    assert_eq!(0, sem.release(1).unwrap());
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert!(fut.poll_unpin(&mut cx).is_ready());
+
+    // Cannot release past the maximum
+    assert_eq!(0, sem.release(2).unwrap());
+    assert!(sem.release(1).is_err());
+}
+
+#[test]
+fn threadpool_test_event_wait_any() {
+    let ev = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let cancel = crate::event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
+    let timeout = Some(std::time::Duration::ZERO);
+
+    // Nothing signaled
+    let err = crate::event::wait_any(&[&ev, &cancel], timeout).unwrap_err();
+    assert!(matches!(err, crate::event::EventError::Timeout));
+
+    // Returns the index of the signaled object
+    cancel.set().unwrap();
+    assert_eq!(1, crate::event::wait_any(&[&ev, &cancel], timeout).unwrap());
+
+    // Lowest index wins when both are signaled
+    ev.set().unwrap();
+    assert_eq!(0, crate::event::wait_any(&[&ev, &cancel], timeout).unwrap());
+
+    // Must wait on at least one object, and at most MAXIMUM_WAIT_OBJECTS
+    assert!(crate::event::wait_any(&[], timeout).is_err());
+    let many = vec![&ev as &dyn crate::event::Waitable; 65];
+    let err = crate::event::wait_any(&many, timeout).unwrap_err();
+    assert!(
+        matches!(err, crate::event::EventError::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput)
+    );
+}
@@ -78,6 +78,119 @@ fn test_threadpool_stream_watch() {
     // All done
     ready.store(2, Ordering::SeqCst);
     assert!(signal.poll_unpin(&mut cx).is_pending());
+    assert!(std::pin::Pin::new(&mut st).try_inner_mut().is_some());
     assert_eq!(Poll::Ready(None), st.poll_next_unpin(&mut cx));
     assert_eq!(Poll::Ready(()), signal.poll_unpin(&mut cx));
+    assert!(std::pin::Pin::new(&mut st).try_inner_mut().is_none());
+}
+
+#[test]
+fn test_threadpool_future_watch_with() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Thing under test
+    let (mut signal, mut fut) = futures::future::ready(1).watch();
+    assert_eq!(Poll::Ready(1), fut.poll_unpin(&mut cx));
+    assert!(signal.is_signaled());
+    assert_eq!(Poll::Ready(()), signal.poll_unpin(&mut cx));
+
+    // Re-arm the same signal with a new future
+    let ready = AtomicBool::new(false);
+    let mock = poll_fn(|_cx| match ready.load(Ordering::SeqCst) {
+        true => Poll::Ready(42),
+        false => Poll::Pending,
+    });
+    let mut fut = mock.watch_with(&signal);
+    assert!(!signal.is_signaled());
+    assert!(fut.poll_unpin(&mut cx).is_pending());
+    assert!(signal.poll_unpin(&mut cx).is_pending());
+
+    // Signal mirrors the new future
+    ready.store(true, Ordering::SeqCst);
+    assert_eq!(Poll::Ready(42), fut.poll_unpin(&mut cx));
+    assert_eq!(Poll::Ready(()), signal.poll_unpin(&mut cx));
+}
+
+#[test]
+fn test_threadpool_stream_until_signal() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // A stream which never ends, and a signal from a future which is not ready yet
+    let ready = AtomicBool::new(false);
+    let (signal, mut fut) = poll_fn(|_cx| match ready.load(Ordering::SeqCst) {
+        true => Poll::Ready(()),
+        false => Poll::Pending,
+    })
+    .watch();
+    let mut st = futures::stream::repeat(42).until_signal(signal);
+
+    // Items pass through until the signal fires
+    assert_eq!(Poll::Ready(Some(42)), st.poll_next_unpin(&mut cx));
+    assert_eq!(Poll::Ready(Some(42)), st.poll_next_unpin(&mut cx));
+    assert!(st.inner().is_some());
+
+    // Stream ends once the signal fires, and stays ended
+    ready.store(true, Ordering::SeqCst);
+    assert_eq!(Poll::Ready(()), fut.poll_unpin(&mut cx));
+    assert_eq!(Poll::Ready(None), st.poll_next_unpin(&mut cx));
+    assert_eq!(Poll::Ready(None), st.poll_next_unpin(&mut cx));
+    assert!(st.inner().is_none());
+}
+
+#[test]
+fn test_threadpool_future_watch_subscribe() {
+    // Count the wakes of each waiter
+    struct Counter(AtomicU8);
+    impl futures::task::ArcWake for Counter {
+        fn wake_by_ref(arc_self: &std::sync::Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let first = std::sync::Arc::new(Counter(AtomicU8::new(0)));
+    let second = std::sync::Arc::new(Counter(AtomicU8::new(0)));
+    let first_waker = futures::task::waker(std::sync::Arc::clone(&first));
+    let second_waker = futures::task::waker(std::sync::Arc::clone(&second));
+
+    // A mock future with two waiters
+    let ready = AtomicBool::new(false);
+    let mock = poll_fn(|_cx| match ready.load(Ordering::SeqCst) {
+        true => Poll::Ready(42),
+        false => Poll::Pending,
+    });
+    let (mut signal, mut fut) = mock.watch();
+    let mut subscriber = signal.subscribe();
+    let mut cx = std::task::Context::from_waker(&first_waker);
+    assert!(signal.poll_unpin(&mut cx).is_pending());
+    let mut cx = std::task::Context::from_waker(&second_waker);
+    assert!(subscriber.poll_unpin(&mut cx).is_pending());
+
+    // Both waiters are woken when the future completes
+    ready.store(true, Ordering::SeqCst);
+    assert_eq!(Poll::Ready(42), fut.poll_unpin(&mut cx));
+    assert_eq!(1, first.0.load(Ordering::SeqCst));
+    assert_eq!(1, second.0.load(Ordering::SeqCst));
+    assert_eq!(Poll::Ready(()), signal.poll_unpin(&mut cx));
+    assert_eq!(Poll::Ready(()), subscriber.poll_unpin(&mut cx));
+
+    // A subscriber made after completion is already signaled
+    assert!(signal.subscribe().is_signaled());
+
+    // Dropped subscribers give their slot to the next subscriber. One slot is still held by
+    // the subscriber above
+    for _ in 0..8 {
+        drop(signal.subscribe());
+    }
+    assert_eq!(2, signal.subscriber_slots());
+    let subscribers = (0..4).map(|_| signal.subscribe()).collect::<Vec<_>>();
+    drop(subscribers);
+    drop(subscriber);
+    assert_eq!(5, signal.subscriber_slots());
+    for _ in 0..5 {
+        drop(signal.subscribe());
+    }
+    assert_eq!(5, signal.subscriber_slots());
 }
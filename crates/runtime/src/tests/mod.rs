@@ -1,2 +1,5 @@
+mod codec;
 mod event;
 mod futures;
+mod timer;
+mod usb;
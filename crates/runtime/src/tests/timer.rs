@@ -0,0 +1,64 @@
+use crate::timer::{system_time_to_filetime, TimerPool, TimerThreadpoolOptions};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use windows_sys::Win32::Foundation::FILETIME;
+
+/// The number of 100ns intervals between the windows epoch (1601-01-01) and the unix epoch
+const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+fn ticks(ft: FILETIME) -> u64 {
+    (ft.dwHighDateTime as u64) << 32 | ft.dwLowDateTime as u64
+}
+
+#[test]
+fn threadpool_test_timer_filetime() {
+    // The unix epoch is offset from the windows epoch
+    assert_eq!(UNIX_EPOCH_TICKS, ticks(system_time_to_filetime(UNIX_EPOCH)));
+
+    // Sub tick precision is truncated
+    let time = UNIX_EPOCH + Duration::from_nanos(1_234);
+    assert_eq!(UNIX_EPOCH_TICKS + 12, ticks(system_time_to_filetime(time)));
+
+    // Times before the windows epoch saturate to the earliest absolute time. (Zero is special)
+    let time = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+    assert_eq!(1, ticks(system_time_to_filetime(time)));
+    let time = UNIX_EPOCH - Duration::from_secs(11_644_473_600 + 60);
+    assert_eq!(1, ticks(system_time_to_filetime(time)));
+
+    // Far future times never overflow into a negative (relative) due time
+    let max = i64::MAX as u64 - UNIX_EPOCH_TICKS;
+    let time = UNIX_EPOCH + Duration::new(max / 10_000_000, (max % 10_000_000 * 100) as u32);
+    assert_eq!(i64::MAX as u64, ticks(system_time_to_filetime(time)));
+}
+
+#[test]
+fn threadpool_test_timer_oneshot_at_past() {
+    let mut pool = TimerPool::new(&TimerThreadpoolOptions::default()).unwrap();
+    let start = Instant::now();
+    let past = SystemTime::now() - Duration::from_secs(60 * 60);
+    futures::executor::block_on(async { pool.oneshot_at(past).await.start().await });
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn threadpool_test_timer_interval_stream_stalled() {
+    use futures::StreamExt;
+    let options = TimerThreadpoolOptions {
+        capacity: 1,
+        ..Default::default()
+    };
+    let mut pool = TimerPool::new(&options).unwrap();
+    let period = Duration::from_millis(10);
+    futures::executor::block_on(async {
+        let mut stream = pool.interval_stream(period, period).await.start();
+
+        // Stall the consumer for several periods. Only one timeout fits in the queue
+        std::thread::sleep(period * 10);
+        let count = stream.next().await.unwrap();
+        assert!(count > 1, "dropped timeouts are counted: {count}");
+
+        // The count is reset once the stream is polled
+        std::thread::sleep(period * 3);
+        let count = stream.next().await.unwrap();
+        assert!(count < 10, "count is reset after polling: {count}");
+    });
+}
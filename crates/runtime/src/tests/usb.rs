@@ -0,0 +1,20 @@
+//! usb
+
+use crate::usb::{Baud, Dcb, DeviceControlSettings, FlowControl, RtsControl};
+
+#[test]
+fn usb_dcb_from_settings() {
+    let settings = DeviceControlSettings {
+        baud: Baud::CBR_9600,
+        flow_control: FlowControl::Hardware,
+        ..Default::default()
+    };
+    let dcb = Dcb::from_settings(&settings);
+    assert_eq!(9600, dcb.as_raw().BaudRate);
+    assert_eq!(8, dcb.as_raw().ByteSize);
+    assert!(dcb.flags().get_fBinary());
+    assert!(dcb.flags().get_fOutxCtsFlow());
+    assert_eq!(RtsControl::Enable, dcb.flags().get_fRtsControl());
+    assert!(!dcb.flags().get_fOutX());
+    assert!(!dcb.flags().get_fInX());
+}
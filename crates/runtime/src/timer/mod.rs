@@ -6,7 +6,7 @@
 //! https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-waitforthreadpooltimercallbacks
 
 use crate::{
-    common::{ThreadpoolCallbackEnvironment, WaitPending},
+    common::{abort_on_unwind, ThreadpoolCallbackEnvironment, ThreadpoolCleanupGroup, WaitPending},
     futures::{FuturesExt, Signal, StreamExt, Watch},
 };
 use crossbeam::queue::ArrayQueue;
@@ -18,11 +18,11 @@ use std::{
     io,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     task::{Context, Poll, Waker},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tracing::{debug, warn};
 use windows_sys::Win32::{
@@ -77,6 +77,7 @@ impl TimerPool {
         let shared = Arc::new(Shared {
             waker: Mutex::new(None),
             timeouts: ArrayQueue::new(options.capacity),
+            dropped: AtomicUsize::new(0),
             stopped: AtomicBool::new(false),
         });
         let window = options
@@ -94,6 +95,16 @@ impl TimerPool {
     /// Create a relative oneshot timer. Will wait for any outstanding timers if any outstanding
     /// timers are pending.
     pub async fn oneshot(&mut self, duration: Duration) -> OneshotTimer<'_> {
+        self.start_oneshot(TimerDue::Relative(duration)).await
+    }
+
+    /// Create an absolute oneshot timer which fires at a wall clock time. A time in the past fires
+    /// immediately. Will wait for any outstanding timers if any outstanding timers are pending.
+    pub async fn oneshot_at(&mut self, time: SystemTime) -> OneshotTimer<'_> {
+        self.start_oneshot(TimerDue::Absolute(time)).await
+    }
+
+    async fn start_oneshot(&mut self, due: TimerDue) -> OneshotTimer<'_> {
         let shared = Arc::clone(&self.shared);
         let (signal, fut) = TimerFuture { shared }.watch();
         if let Some(signal) = self.timer.replace(signal) {
@@ -103,7 +114,7 @@ impl TimerPool {
         self.shared.reset();
         OneshotTimer {
             fut,
-            due: duration,
+            due,
             window: self.window,
             pool: &self.pool,
         }
@@ -113,7 +124,33 @@ impl TimerPool {
     /// outstanding timers are pending.
     pub async fn periodic(&mut self, duration: Duration, period: Duration) -> PeriodicTimer<'_> {
         let shared = Arc::clone(&self.shared);
-        let (signal, stream) = TimerStream { shared }.watch();
+        self.start_periodic(TimerStream { shared }, duration, period)
+            .await
+    }
+
+    /// Start a stream of periodic timer events which yields the number of timeouts that elapsed
+    /// since the stream was last polled, including timeouts dropped because the queue was full.
+    /// Will wait for any outstanding timers if any outstanding timers are pending.
+    pub async fn interval_stream(
+        &mut self,
+        duration: Duration,
+        period: Duration,
+    ) -> PeriodicTimer<'_, CountingTimerStream> {
+        let shared = Arc::clone(&self.shared);
+        self.start_periodic(CountingTimerStream { shared }, duration, period)
+            .await
+    }
+
+    async fn start_periodic<S>(
+        &mut self,
+        stream: S,
+        duration: Duration,
+        period: Duration,
+    ) -> PeriodicTimer<'_, S>
+    where
+        S: Stream,
+    {
+        let (signal, stream) = stream.watch();
         if let Some(signal) = self.timer.replace(signal) {
             warn!("waiting for previous timer to finished before starting perodic timer");
             signal.await;
@@ -134,33 +171,78 @@ impl TimerPool {
         self.shared.stop().maybe_wake_by_ref();
         self
     }
+
+    /// Hand the timer to the cleanup group it was created in. The timer is closed when the
+    /// members of the group are closed, see [`ThreadpoolCleanupGroup::close_members`]
+    ///
+    /// NOTE the pool must have been created with an environment associated with this group
+    pub fn into_group_member(self, group: &ThreadpoolCleanupGroup) {
+        let TimerPool { pool, shared, .. } = self;
+        // NOTE the group closes the handle
+        pool.into_raw();
+        group.hold(shared);
+    }
+}
+
+/// When a oneshot timer is due
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TimerDue {
+    /// Due after a duration from when the timer was started
+    Relative(Duration),
+    /// Due at a wall clock time
+    Absolute(SystemTime),
 }
 
 pub struct OneshotTimer<'pool> {
     fut: Watch<TimerFuture>,
-    due: Duration,
+    due: TimerDue,
     window: u32,
     pool: &'pool OwnedTimerHandle,
 }
 
 impl<'pool> OneshotTimer<'pool> {
     pub fn start(self) -> Watch<TimerFuture> {
-        debug!(duration=?self.due, "starting oneshot timer");
-        self.pool.start_relative(self.due, 0, self.window);
+        debug!(due=?self.due, "starting oneshot timer");
+        match self.due {
+            TimerDue::Relative(due) => self.pool.start_relative(due, 0, self.window),
+            TimerDue::Absolute(time) => {
+                self.pool
+                    .start_absolute(system_time_to_filetime(time), 0, self.window)
+            }
+        }
         self.fut
     }
 }
 
-pub struct PeriodicTimer<'pool> {
-    stream: Watch<TimerStream>,
+/// The number of 100ns intervals between the windows epoch (1601-01-01) and the unix epoch
+const UNIX_EPOCH_TICKS: u64 = 11_644_473_600 * 10_000_000;
+
+/// Convert a wall clock time to an absolute FILETIME. Times before the windows epoch saturate to
+/// the earliest absolute time, which is in the past and therefore due immediately
+pub(crate) fn system_time_to_filetime(time: SystemTime) -> FILETIME {
+    let ticks = |dur: Duration| u64::try_from(dur.as_nanos() / 100).unwrap_or(u64::MAX);
+    let ticks = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH_TICKS.saturating_add(ticks(since)),
+        Err(before) => UNIX_EPOCH_TICKS.saturating_sub(ticks(before.duration())),
+    };
+    // NOTE a due time of zero has a special meaning. The earliest absolute time is 1 tick
+    let ticks = ticks.clamp(1, i64::MAX as u64);
+    FILETIME {
+        dwLowDateTime: (ticks & 0xFFFFFFFF) as u32,
+        dwHighDateTime: (ticks >> 32) as u32,
+    }
+}
+
+pub struct PeriodicTimer<'pool, S = TimerStream> {
+    stream: Watch<S>,
     due: Duration,
     period: Duration,
     window: u32,
     pool: &'pool OwnedTimerHandle,
 }
 
-impl<'pool> PeriodicTimer<'pool> {
-    pub fn start(self) -> Watch<TimerStream> {
+impl<'pool, S> PeriodicTimer<'pool, S> {
+    pub fn start(self) -> Watch<S> {
         debug!(duration=?self.due, period=?self.period, "starting periodic timer");
         let period = self.period.as_millis() as _;
         self.pool.start_relative(self.due, period, self.window);
@@ -207,11 +289,36 @@ impl Stream for TimerStream {
     }
 }
 
+/// Like [`TimerStream`] except yields the number of timeouts that elapsed since the stream was
+/// last polled. Timeouts which did not fit in the queue are counted as well.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CountingTimerStream {
+    shared: Arc<Shared>,
+}
+
+impl Stream for CountingTimerStream {
+    type Item = usize;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.shared.is_stopped() {
+            Poll::Ready(None)
+        } else {
+            self.shared.update_waker(cx.waker());
+            match self.shared.drain() {
+                0 => Poll::Pending,
+                n => Poll::Ready(Some(n)),
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Shared {
     waker: Mutex<Option<Waker>>,
     stopped: AtomicBool,
     timeouts: ArrayQueue<()>,
+    /// The number of timeouts which fired while the queue was full
+    dropped: AtomicUsize,
 }
 
 impl Shared {
@@ -230,10 +337,21 @@ impl Shared {
     }
 
     fn fire(&self) -> &Self {
-        let _ = self.timeouts.push(());
+        if self.timeouts.push(()).is_err() {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
         self
     }
 
+    /// Drain the queue and return the number of timeouts fired, including dropped timeouts
+    fn drain(&self) -> usize {
+        let mut count = self.dropped.swap(0, Ordering::SeqCst);
+        while self.timeouts.pop().is_some() {
+            count += 1;
+        }
+        count
+    }
+
     fn stop(&self) -> &Self {
         self.stopped.store(true, Ordering::SeqCst);
         self
@@ -252,7 +370,7 @@ impl Shared {
 
     fn reset(&self) -> &Self {
         self.stopped.store(false, Ordering::SeqCst);
-        while let Some(_) = self.timeouts.pop() {}
+        self.drain();
         self
     }
 }
@@ -267,6 +385,11 @@ impl Drop for OwnedTimerHandle {
 }
 
 impl OwnedTimerHandle {
+    /// Release ownership of the handle without closing it, IE: when a cleanup group closes it
+    pub(in crate::timer) fn into_raw(self) -> PTP_TIMER {
+        std::mem::ManuallyDrop::new(self).0
+    }
+
     /// Create a new threadpool timer object
     ///
     /// See also:
@@ -301,6 +424,15 @@ impl OwnedTimerHandle {
         unsafe { SetThreadpoolTimer(self.0, &ft as *const _, period, window) }
     }
 
+    /// Start a timer which is due at an absolute time. A positive FILETIME is an absolute time in
+    /// 100ns intervals since 1601-01-01 UTC.
+    ///
+    /// See also:
+    /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpooltimer
+    pub(in crate::timer) fn start_absolute(&self, due: FILETIME, period: u32, window: u32) {
+        unsafe { SetThreadpoolTimer(self.0, &due as *const _, period, window) }
+    }
+
     /// Waits for outstanding timer callbacks to complete and optionally cancels pending callbacks
     /// that have not yet started to execute.
     pub(in crate::timer) fn wait(&self, pending: WaitPending) {
@@ -313,6 +445,8 @@ unsafe extern "system" fn timer_callback(
     context: *mut c_void,
     _wait: PTP_TIMER,
 ) {
-    let cx = unsafe { &*(context as *const Shared) };
-    cx.fire().maybe_wake_by_ref();
+    abort_on_unwind("timer_callback", || {
+        let cx = unsafe { &*(context as *const Shared) };
+        cx.fire().maybe_wake_by_ref();
+    })
 }
@@ -1,318 +1,261 @@
 //! Asyncronously open a USB device
+pub mod dcb;
+
+pub use dcb::{
+    Baud, CommTimeouts, Dcb, DcbFlags, DeviceControlSettings, DtrControl, FlowControl, Parity,
+    RtsControl, Stop,
+};
+
+use crate::timer::TimerPool;
+use bitflags::bitflags;
 use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
-use std::{fmt, io, os::windows::io::AsRawHandle};
-use windows_sys::Win32::{Devices::Communication::*, System::WindowsProgramming::*};
+use std::{io, os::windows::io::AsRawHandle, time::Duration};
+use tracing::warn;
+use windows_sys::Win32::Devices::Communication::*;
 
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum DtrControl {
-    Disable = DTR_CONTROL_DISABLE,
-    Enable = DTR_CONTROL_ENABLE,
-    Handshake = DTR_CONTROL_HANDSHAKE,
-}
-impl DtrControl {
-    pub fn raw(&self) -> u32 {
-        // safety: https://doc.rust-lang.org/reference/items/enumerations.html#pointer-casting
-        // If the enumeration specifies a primitive representation, then the discriminant may
-        // be reliably accessed via unsafe pointer casting:
-        unsafe { *(self as *const Self as *const u32) }
+pub fn configure<H: AsRawHandle>(handle: H, config: DeviceControlSettings) -> io::Result<H> {
+    let mut dcb: DCB = unsafe { std::mem::zeroed() };
+    match unsafe { GetCommState(handle.as_raw_handle() as _, &mut dcb) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }?;
+    let mut dcb = Dcb::from(dcb);
+    dcb.apply(&config);
+    match unsafe { SetCommState(handle.as_raw_handle() as _, dcb.as_raw()) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }?;
+    if config.verify {
+        verify_flow_control(&handle, dcb.flags(), config.flow_control)?;
     }
-}
 
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum RtsControl {
-    Disable = RTS_CONTROL_DISABLE,
-    Enable = RTS_CONTROL_ENABLE,
-    Handshake = RTS_CONTROL_HANDSHAKE,
-    Toggle = RTS_CONTROL_TOGGLE,
-}
-impl RtsControl {
-    pub fn raw(&self) -> u32 {
-        // safety: https://doc.rust-lang.org/reference/items/enumerations.html#pointer-casting
-        // If the enumeration specifies a primitive representation, then the discriminant may
-        // be reliably accessed via unsafe pointer casting:
-        unsafe { *(self as *const Self as *const u32) }
+    // Set timeouts
+    let timeouts = COMMTIMEOUTS::from(config.timeouts);
+    match unsafe { SetCommTimeouts(handle.as_raw_handle() as _, &timeouts) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(handle),
     }
 }
 
-macro_rules! impl_set_bits {
-    ($name:ident, $offset:path) => {
-        #[allow(non_snake_case)]
-        pub fn $name(&mut self, val: bool) -> &mut Self {
-            match val {
-                true => self.0 |= 0x01 << $offset,
-                false => self.0 &= !(0x01 << $offset),
-            };
-            self
-        }
-    };
-
-    ($name:ident, $value:ty, $offset:path) => {
-        #[allow(non_snake_case)]
-        pub fn $name(&mut self, val: $value) -> &mut Self {
-            self.0 &= !(0x03 << $offset);
-            self.0 |= ((val as u32) << $offset) as u32;
-            self
-        }
-    };
+/// Read the device state back and compare the flow control bits against what we requested
+fn verify_flow_control<H: AsRawHandle>(
+    handle: &H,
+    expect: DcbFlags,
+    flow_control: FlowControl,
+) -> io::Result<()> {
+    let mut dcb: DCB = unsafe { std::mem::zeroed() };
+    match unsafe { GetCommState(handle.as_raw_handle() as _, &mut dcb) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }?;
+    let actual = DcbFlags::new(dcb._bitfield);
+    match actual.get_fOutxCtsFlow() == expect.get_fOutxCtsFlow()
+        && actual.get_fRtsControl() == expect.get_fRtsControl()
+        && actual.get_fOutX() == expect.get_fOutX()
+        && actual.get_fInX() == expect.get_fInX()
+    {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("device rejected flow control {flow_control:?} {actual:?}"),
+        )),
+    }
 }
 
-macro_rules! impl_get_bits {
-    ($name:ident, $offset:path) => {
-        #[allow(non_snake_case)]
-        pub fn $name(&self) -> bool {
-            self.0 & (0x01 << $offset) > 0
-        }
-    };
-
-    ($name:ident, $value:ident, $offset:path) => {
-        #[allow(non_snake_case)]
-        pub fn $name(&self) -> $value {
-            let mut raw = (self.0 & (0x03 << $offset));
-            raw >>= $offset;
-            // Safety: We control these bits exclusivly so we know they are initialized
-            unsafe { $value::from_u32(raw).unwrap_unchecked() }
-        }
-    };
+bitflags! {
+    /// Communication errors reported by the serial driver. The driver latches these errors until
+    /// they are cleared with [`clear_comm_error`].
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-clearcommerror)
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct CommErrorFlags: u32 {
+        /// The hardware detected a break condition
+        const BREAK = CE_BREAK;
+        /// The hardware detected a framing error
+        const FRAME = CE_FRAME;
+        /// A character-buffer overrun has occurred. The next character is lost
+        const OVERRUN = CE_OVERRUN;
+        /// An input buffer overflow has occurred. Data was received after the input buffer was full
+        const RXOVER = CE_RXOVER;
+        /// The hardware detected a parity error
+        const RXPARITY = CE_RXPARITY;
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
-pub struct DcbFlags(u32);
-impl DcbFlags {
-    pub const FBINARY: u8 = 0x00;
-    pub const FPARITY: u8 = 0x01;
-    pub const FOUTXCTSFLOW: u8 = 0x02;
-    pub const FOUTXDSRFLOW: u8 = 0x03;
-    pub const FDTRCONTROL: u8 = 0x04;
-    pub const FDSRSENSITIVITY: u8 = 0x06;
-    pub const FTXCONTINUEONXOFF: u8 = 0x07;
-    pub const FOUTX: u8 = 0x08;
-    pub const FINX: u8 = 0x09;
-    pub const FERRORCHAR: u8 = 0x0A;
-    pub const FNULL: u8 = 0x0B;
-    pub const FRTSCONTROL: u8 = 0x0C;
-    pub const FABORTONERROR: u8 = 0x0E;
-
-    pub fn new(val: u32) -> Self {
-        Self(val)
+impl CommErrorFlags {
+    /// The driver dropped incoming bytes because reads were not issued fast enough
+    pub fn is_overrun(&self) -> bool {
+        self.intersects(Self::OVERRUN | Self::RXOVER)
     }
+}
 
-    pub fn value(&self) -> u32 {
-        self.0
+/// Read and clear any communication errors latched by the serial driver. While an error is
+/// latched the driver may refuse further I/O, so callers that care about lost bytes should call
+/// this after each read completes and check [`CommErrorFlags::is_overrun`].
+///
+/// NOTE this is a syscall, so it is not free to call on every read.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-clearcommerror)
+pub fn clear_comm_error<H: AsRawHandle>(handle: &H) -> io::Result<CommErrorFlags> {
+    let mut errors = 0;
+    let result = unsafe {
+        ClearCommError(
+            handle.as_raw_handle() as _,
+            &mut errors,
+            std::ptr::null_mut(),
+        )
+    };
+    match result {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(CommErrorFlags::from_bits_retain(errors)),
     }
-
-    impl_set_bits!(set_fBinary, Self::FBINARY);
-    impl_set_bits!(set_fParity, Self::FPARITY);
-    impl_set_bits!(set_fOutxCtsFlow, Self::FOUTXCTSFLOW);
-    impl_set_bits!(set_fOutxDsrFlow, Self::FOUTXDSRFLOW);
-    impl_set_bits!(set_fDsrSensitivity, Self::FDSRSENSITIVITY);
-    impl_set_bits!(set_fTXContinueOnXoff, Self::FTXCONTINUEONXOFF);
-    impl_set_bits!(set_fOutX, Self::FOUTX);
-    impl_set_bits!(set_fInX, Self::FINX);
-    impl_set_bits!(set_fErrorChar, Self::FERRORCHAR);
-    impl_set_bits!(set_fNull, Self::FNULL);
-    impl_set_bits!(set_fAbortOnError, Self::FABORTONERROR);
-    impl_set_bits!(set_fDtrControl, DtrControl, Self::FDTRCONTROL);
-    impl_set_bits!(set_fRtsControl, RtsControl, Self::FRTSCONTROL);
-
-    impl_get_bits!(get_fBinary, Self::FBINARY);
-    impl_get_bits!(get_fParity, Self::FPARITY);
-    impl_get_bits!(get_fOutxCtsFlow, Self::FOUTXCTSFLOW);
-    impl_get_bits!(get_fOutxDsrFlow, Self::FOUTXDSRFLOW);
-    impl_get_bits!(get_fDsrSensitivity, Self::FDSRSENSITIVITY);
-    impl_get_bits!(get_fTXContinueOnXoff, Self::FTXCONTINUEONXOFF);
-    impl_get_bits!(get_fOutX, Self::FOUTX);
-    impl_get_bits!(get_fInX, Self::FINX);
-    impl_get_bits!(get_fErrorChar, Self::FERRORCHAR);
-    impl_get_bits!(get_fNull, Self::FNULL);
-    impl_get_bits!(get_fAbortOnError, Self::FABORTONERROR);
-    impl_get_bits!(get_fDtrControl, DtrControl, Self::FDTRCONTROL);
-    impl_get_bits!(get_fRtsControl, RtsControl, Self::FRTSCONTROL);
 }
 
-impl fmt::Debug for DcbFlags {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("DcbFlags")
-            .field("fBinary", &self.get_fBinary())
-            .field("fParity", &self.get_fParity())
-            .field("fOutxCtsFlow", &self.get_fOutxCtsFlow())
-            .field("fOutxDsrFlow", &self.get_fOutxDsrFlow())
-            .field("fDsrSensitify", &self.get_fDsrSensitivity())
-            .field("fOutX", &self.get_fOutX())
-            .field("fErrorChar", &self.get_fErrorChar())
-            .field("fNull", &self.get_fNull())
-            .field("fAbortOnError", &self.get_fAbortOnError())
-            .field("fDtrControl", &self.get_fDtrControl())
-            .field("fRtsControl", &self.get_fRtsControl())
-            .finish()
+bitflags! {
+    /// Which driver buffers to discard with [`purge`]
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-purgecomm)
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PurgeFlags: u32 {
+        /// Terminate all outstanding overlapped read operations
+        const RXABORT = PURGE_RXABORT;
+        /// Clear the input buffer
+        const RXCLEAR = PURGE_RXCLEAR;
+        /// Terminate all outstanding overlapped write operations
+        const TXABORT = PURGE_TXABORT;
+        /// Clear the output buffer
+        const TXCLEAR = PURGE_TXCLEAR;
     }
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum Parity {
-    None = NOPARITY,
-    Even = EVENPARITY,
-    Odd = ODDPARITY,
-    Mark = MARKPARITY,
-    Space = SPACEPARITY,
+/// Discard bytes sitting in the driver's input and/or output buffers. Useful to drop stale data
+/// after resynchronizing with a device.
+///
+/// NOTE [`PurgeFlags::RXABORT`] and [`PurgeFlags::TXABORT`] complete any outstanding overlapped
+/// operation with `ERROR_OPERATION_ABORTED`. Readers issuing overlapped reads on the same handle
+/// must tolerate that error and issue another read.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-purgecomm)
+pub fn purge<H: AsRawHandle>(handle: &H, flags: PurgeFlags) -> io::Result<()> {
+    match unsafe { PurgeComm(handle.as_raw_handle() as _, flags.bits()) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
 }
 
+/// Modem control lines driven with [`escape_comm_function`]
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-escapecommfunction)
 #[repr(u32)]
-#[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum Baud {
-    CBR_110 = CBR_110,
-    CBR_300 = CBR_300,
-    CBR_600 = CBR_600,
-    CBR_1200 = CBR_1200,
-    CBR_2400 = CBR_2400,
-    CBR_4800 = CBR_4800,
-    CBR_9600 = CBR_9600,
-    CBR_14400 = CBR_14400,
-    CBR_19200 = CBR_19200,
-    CBR_38400 = CBR_38400,
-    CBR_57600 = CBR_57600,
-    CBR_115200 = CBR_115200,
-    CBR_128000 = CBR_128000,
-    CBR_256000 = CBR_256000,
+pub enum EscapeFunction {
+    /// Sends the DTR (data-terminal-ready) signal
+    SetDtr = SETDTR,
+    /// Clears the DTR (data-terminal-ready) signal
+    ClrDtr = CLRDTR,
+    /// Sends the RTS (request-to-send) signal
+    SetRts = SETRTS,
+    /// Clears the RTS (request-to-send) signal
+    ClrRts = CLRRTS,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-#[repr(u8)]
-pub enum Stop {
-    One = ONESTOPBIT,
-    One5 = ONE5STOPBITS,
-    Two = TWOSTOPBITS,
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum FlowControl {
-    None,
-    Software,
-    Hardware,
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub struct DeviceControlSettings {
-    pub baud: Baud,
-    pub bytes: u8,
-    pub parity: Parity,
-    pub stop: Stop,
-    pub flow_control: FlowControl,
+/// Direct the device to perform an extended function, such as toggling DTR to reset a
+/// bootloader. This does not interact with overlapped I/O and is safe to call while reads are
+/// pending.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-escapecommfunction)
+pub fn escape_comm_function<H: AsRawHandle>(handle: &H, func: EscapeFunction) -> io::Result<()> {
+    match unsafe { EscapeCommFunction(handle.as_raw_handle() as _, func as _) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
 }
 
-impl Default for DeviceControlSettings {
-    fn default() -> Self {
-        Self {
-            baud: Baud::CBR_115200,
-            bytes: 8,
-            parity: Parity::None,
-            stop: Stop::One,
-            flow_control: FlowControl::None,
-        }
+bitflags! {
+    /// State of the modem control input lines
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommmodemstatus)
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct ModemStatus: u32 {
+        /// The CTS (clear-to-send) signal is on
+        const CTS_ON = MS_CTS_ON;
+        /// The DSR (data-set-ready) signal is on
+        const DSR_ON = MS_DSR_ON;
+        /// The ring indicator signal is on
+        const RING_ON = MS_RING_ON;
+        /// The RLSD (receive-line-signal-detect) signal is on
+        const RLSD_ON = MS_RLSD_ON;
     }
 }
 
-/// https://learn.microsoft.com/en-us/windows/win32/api/winbase/ns-winbase-dcb
-pub struct Dcb(DCB);
-impl fmt::Debug for Dcb {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let baud = Baud::from_u32(self.0.BaudRate).ok_or_else(std::fmt::Error::default)?;
-        let parity = Parity::from_u8(self.0.Parity).ok_or_else(std::fmt::Error::default)?;
-        let stop = Stop::from_u8(self.0.StopBits).ok_or_else(std::fmt::Error::default)?;
-        let flags = DcbFlags::new(self.0._bitfield);
-        f.debug_struct("Dcb")
-            .field("BaudRate", &baud)
-            .field("Parity", &parity)
-            .field("StopBits", &stop)
-            .field("fBinary", &flags.get_fBinary())
-            .field("fParity", &flags.get_fParity())
-            .field("fOutxCtsFlow", &flags.get_fOutxCtsFlow())
-            .field("fOutxDsrFlow", &flags.get_fOutxDsrFlow())
-            .field("fDsrSensitify", &flags.get_fDsrSensitivity())
-            .field("fOutX", &flags.get_fOutX())
-            .field("fErrorChar", &flags.get_fErrorChar())
-            .field("fNull", &flags.get_fNull())
-            .field("fAbortOnError", &flags.get_fAbortOnError())
-            .field("fDtrControl", &flags.get_fDtrControl())
-            .field("fRtsControl", &flags.get_fRtsControl())
-            .field("ByteSize", &self.0.ByteSize)
-            .field("XonLim", &self.0.XonLim)
-            .field("XoffLim", &self.0.XoffLim)
-            .field("XOnChar", &self.0.XonChar)
-            .field("XoffChar", &self.0.XoffChar)
-            .field("ErrorChar", &self.0.ErrorChar)
-            .field("EofChar", &self.0.EofChar)
-            .field("EvtChar", &self.0.EvtChar)
-            .finish()
+/// Read the modem control input lines. This does not interact with overlapped I/O and is safe to
+/// call while reads are pending.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getcommmodemstatus)
+pub fn modem_status<H: AsRawHandle>(handle: &H) -> io::Result<ModemStatus> {
+    let mut status = 0;
+    match unsafe { GetCommModemStatus(handle.as_raw_handle() as _, &mut status) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(ModemStatus::from_bits_retain(status)),
     }
 }
 
-pub fn configure<H: AsRawHandle>(handle: H, config: DeviceControlSettings) -> io::Result<H> {
-    let mut dcb: DCB = unsafe { std::mem::zeroed() };
-    match unsafe { GetCommState(handle.as_raw_handle() as _, &mut dcb) } {
+/// Suspend character transmission and place the transmission line in a break state until
+/// [`break_off`] is called, IE: to wake a sleeping MCU. A break is a line level operation and does
+/// not disturb overlapped I/O. Pending writes are held by the driver until the break is cleared.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-setcommbreak)
+pub fn break_on<H: AsRawHandle>(handle: &H) -> io::Result<()> {
+    match unsafe { SetCommBreak(handle.as_raw_handle() as _) } {
         0 => Err(io::Error::last_os_error()),
         _ => Ok(()),
-    }?;
-    // Set some defaults
-    // https://github.com/serialport/serialport-rs/blob/main/src/windows/dcb.rs
-    dcb.XonChar = 17;
-    dcb.XoffChar = 19;
-    dcb.ErrorChar = b'\0';
-    dcb.EofChar = 26;
-    // Set the bitfields
-    let mut flags = DcbFlags::new(dcb._bitfield);
-    flags.set_fBinary(true);
-    flags.set_fOutxDsrFlow(false);
-    flags.set_fDtrControl(DtrControl::Enable);
-    flags.set_fDsrSensitivity(false);
-    flags.set_fErrorChar(false);
-    flags.set_fNull(false);
-    flags.set_fAbortOnError(false);
-    match config.flow_control {
-        FlowControl::None => {
-            flags.set_fOutxCtsFlow(false);
-            flags.set_fRtsControl(RtsControl::Disable);
-            flags.set_fOutX(false);
-            flags.set_fInX(false);
-        }
-        FlowControl::Software => {
-            flags.set_fOutxCtsFlow(false);
-            flags.set_fRtsControl(RtsControl::Disable);
-            flags.set_fOutX(true);
-            flags.set_fInX(true);
-        }
-        FlowControl::Hardware => {
-            flags.set_fOutxCtsFlow(true);
-            flags.set_fRtsControl(RtsControl::Enable);
-            flags.set_fOutX(false);
-            flags.set_fInX(false);
-        }
     }
-    dcb._bitfield = flags.0;
-    // Set user configurations
-    dcb.BaudRate = config.baud as _;
-    dcb.ByteSize = config.bytes;
-    dcb.Parity = config.parity as _;
-    dcb.StopBits = config.stop as _;
-    match unsafe { SetCommState(handle.as_raw_handle() as _, &mut dcb) } {
+}
+
+/// Restore character transmission after [`break_on`]
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-clearcommbreak)
+pub fn break_off<H: AsRawHandle>(handle: &H) -> io::Result<()> {
+    match unsafe { ClearCommBreak(handle.as_raw_handle() as _) } {
         0 => Err(io::Error::last_os_error()),
         _ => Ok(()),
-    }?;
+    }
+}
 
-    // Set timeouts
-    let timeouts = COMMTIMEOUTS {
-        ReadIntervalTimeout: 100,
-        ReadTotalTimeoutMultiplier: 0,
-        ReadTotalTimeoutConstant: 0,
-        WriteTotalTimeoutConstant: 0,
-        WriteTotalTimeoutMultiplier: 0,
-    };
-    match unsafe { SetCommTimeouts(handle.as_raw_handle() as _, &timeouts) } {
-        0 => Err(io::Error::last_os_error()),
-        _ => Ok(handle),
+/// Hold the line in a break state for a duration. See [`break_on`]
+///
+/// NOTE if this future is dropped before it completes, the break state is cleared on drop so a
+/// cancelled break can not leave the line in the break state
+pub async fn send_break<H: AsRawHandle>(
+    handle: &H,
+    pool: &mut TimerPool,
+    duration: Duration,
+) -> io::Result<()> {
+    break_on(handle)?;
+    let guard = BreakGuard(Some(handle));
+    pool.oneshot(duration).await.start().await;
+    guard.release()
+}
+
+/// Clears the break state set by [`send_break`] when dropped
+struct BreakGuard<'h, H: AsRawHandle>(Option<&'h H>);
+
+impl<H: AsRawHandle> BreakGuard<'_, H> {
+    /// Clear the break state and report the error instead of logging it
+    fn release(mut self) -> io::Result<()> {
+        self.0.take().map_or(Ok(()), break_off)
+    }
+}
+
+impl<H: AsRawHandle> Drop for BreakGuard<'_, H> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            if let Err(error) = break_off(handle) {
+                warn!(?error, "failed to clear break state");
+            }
+        }
     }
 }
@@ -4,7 +4,7 @@
 //! https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpoolwait
 //! https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-waitforthreadpoolwaitcallbacks
 
-use crate::common::{ThreadpoolCallbackEnvironment, WaitPending};
+use crate::common::{abort_on_unwind, ThreadpoolCallbackEnvironment, WaitPending};
 use parking_lot::Mutex;
 use std::{
     error,
@@ -15,10 +15,11 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll, Waker},
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tracing::error;
 use windows_sys::Win32::{
-    Foundation::{FILETIME, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT},
+    Foundation::{FILETIME, HANDLE, WAIT_ABANDONED_0, WAIT_OBJECT_0, WAIT_TIMEOUT},
     System::Threading::{
         CloseThreadpoolWait, CreateThreadpoolWait, SetThreadpoolWait,
         WaitForThreadpoolWaitCallbacks, PTP_CALLBACK_INSTANCE, PTP_WAIT,
@@ -37,6 +38,11 @@ pub enum WaitError {
     Timeout,
     /// Already waiting for the waitable object
     InProgress,
+    /// The waitable object is a mutex which was not released by the thread that owned it before
+    /// the thread terminated
+    Abandoned,
+    /// The wait resolved with a result which is not documented for a threadpool wait
+    Unexpected(u32),
 }
 
 impl fmt::Display for WaitError {
@@ -45,6 +51,8 @@ impl fmt::Display for WaitError {
             WaitError::Timeout => write!(f, "wait timeout"),
             WaitError::Cancelled => write!(f, "wait cancelled"),
             WaitError::InProgress => write!(f, "wait already in progress"),
+            WaitError::Abandoned => write!(f, "wait abandoned"),
+            WaitError::Unexpected(result) => write!(f, "unexpected wait result {result}"),
         }
     }
 }
@@ -97,6 +105,7 @@ impl WaitPool {
     pub fn start(&mut self, handle: HANDLE, timeout: Option<Duration>) -> WaitFuture {
         if !self.started {
             self.started = true;
+            self.shared.lock().arm(timeout);
             self.pool.start(handle, timeout);
             WaitFuture {
                 shared: Arc::clone(&self.shared),
@@ -117,6 +126,7 @@ impl WaitPool {
     ) -> Result<WaitFuture, WaitError> {
         let mut shared = self.shared.lock();
         let _old = shared.result.take().ok_or(WaitError::InProgress)?;
+        shared.arm(timeout);
         self.pool.start(handle, timeout);
         Ok(WaitFuture {
             shared: Arc::clone(&self.shared),
@@ -133,11 +143,141 @@ impl WaitPool {
     }
 }
 
+/// A single wait registration of a [`MultiWaitPool`]. Each registration owns its own threadpool
+/// wait object and shared state, so the kernel routes each callback to the correct state.
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857)
+struct WaitRegistration {
+    /// The threadpool wait object must drop first and wait for callbacks to finish
+    pool: OwnedWaitHandle,
+    /// Shared state between the wait callback and the future waiting for the wait object
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl WaitRegistration {
+    fn is_pending(&self) -> bool {
+        self.shared.lock().result.is_none()
+    }
+}
+
+/// Like [`WaitPool`] except can wait on many wait objects concurrently. Every call to
+/// [`MultiWaitPool::start`] registers a new threadpool wait object in the same callback
+/// environment, and returns a [`WaitFuture`] for that wait object.
+pub struct MultiWaitPool<'env> {
+    /// An optional private threadpool configuration for every registration
+    env: Option<&'env ThreadpoolCallbackEnvironment>,
+    /// Every wait object we are waiting on
+    registrations: Vec<WaitRegistration>,
+}
+
+impl<'env> MultiWaitPool<'env> {
+    pub fn new() -> Self {
+        Self {
+            env: None,
+            registrations: Vec::new(),
+        }
+    }
+
+    pub fn with_environment(env: &'env ThreadpoolCallbackEnvironment) -> Self {
+        Self {
+            env: Some(env),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Return a future that resolves when the wait object is completed. Each call registers a new
+    /// threadpool wait object, and closes the wait objects of registrations which have completed.
+    ///
+    /// See also [`OwnedWaitHandle::start`]
+    pub fn start(&mut self, handle: HANDLE, timeout: Option<Duration>) -> io::Result<WaitFuture> {
+        self.prune();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let pool = OwnedWaitHandle::new(self.env, Arc::as_ptr(&shared) as _)?;
+        shared.lock().arm(timeout);
+        pool.start(handle, timeout);
+        let fut = WaitFuture {
+            shared: Arc::clone(&shared),
+        };
+        self.registrations.push(WaitRegistration { pool, shared });
+        Ok(fut)
+    }
+
+    /// The number of wait objects this pool is still waiting on
+    pub fn len(&self) -> usize {
+        self.registrations
+            .iter()
+            .filter(|registration| registration.is_pending())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Close the threadpool wait objects of completed registrations. The futures of completed
+    /// registrations keep their result
+    pub fn prune(&mut self) -> &mut Self {
+        // NOTE the lock is released before a registration is dropped, so a callback which is
+        // still running can finish while the drop waits for it
+        self.registrations
+            .retain(|registration| registration.is_pending());
+        self
+    }
+
+    /// Resolve every pending WaitFuture with a [`WaitError::Cancelled`]
+    pub fn cancel_all(&self) -> &Self {
+        for registration in &self.registrations {
+            registration.pool.stop();
+            registration
+                .shared
+                .lock()
+                .maybe_wake_with(Err(WaitError::Cancelled));
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+impl MultiWaitPool<'_> {
+    /// The number of registrations, including completed registrations which are not pruned yet
+    pub(crate) fn registrations(&self) -> usize {
+        self.registrations.len()
+    }
+}
+
+impl Default for MultiWaitPool<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WaitFuture {
     shared: Arc<Mutex<Shared>>,
 }
 
+impl WaitFuture {
+    /// The time elapsed since the wait was started
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.shared.lock().started.map(|started| started.elapsed())
+    }
+
+    /// The timeout requested when the wait was started
+    pub fn timeout(&self) -> Option<Duration> {
+        self.shared.lock().timeout
+    }
+
+    /// The time remaining before the requested timeout elapses. Useful to re-arm a wait with the
+    /// remainder of the original timeout
+    pub fn remaining(&self) -> Option<Duration> {
+        let shared = self.shared.lock();
+        match (shared.started, shared.timeout) {
+            (Some(started), Some(timeout)) => Some(timeout.saturating_sub(started.elapsed())),
+            _ => None,
+        }
+    }
+}
+
 impl Future for WaitFuture {
     type Output = WaitResult;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -171,9 +311,18 @@ impl Future for WaitFuture {
 pub struct Shared {
     waker: Option<Waker>,
     result: Option<WaitResult>,
+    /// When the wait was started
+    started: Option<Instant>,
+    /// The timeout requested when the wait was started
+    timeout: Option<Duration>,
 }
 
 impl Shared {
+    fn arm(&mut self, timeout: Option<Duration>) {
+        self.started = Some(Instant::now());
+        self.timeout = timeout;
+    }
+
     fn maybe_wake_with(&mut self, result: WaitResult) {
         match self.result.replace(result) {
             Some(result) => self.result = Some(result),
@@ -209,20 +358,21 @@ impl OwnedWaitHandle {
         }
     }
 
-    /// Set a wait object on a threadpool which will trigger a wait callback
+    /// Set a wait object on a threadpool which will trigger a wait callback. The timeout is
+    /// relative to when the wait is started.
     ///
     /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpoolwait
     pub(in crate::wait) fn start(&self, handle: HANDLE, timeout: Option<Duration>) {
-        let ft = timeout
-            .map(|to| {
-                let ms = to.as_millis();
-                &FILETIME {
-                    dwHighDateTime: (ms >> 32) as u32,
-                    dwLowDateTime: (ms & 0xFFFFFFFF) as u32,
-                } as *const _
-            })
-            .unwrap_or_else(std::ptr::null);
-        unsafe { SetThreadpoolWait(self.0, handle, ft) };
+        // NOTE a negative FILETIME is a relative timeout in 100ns intervals
+        let ft = timeout.map(|to| {
+            let ticks = -i64::try_from(to.as_nanos() / 100).unwrap_or(i64::MAX);
+            FILETIME {
+                dwHighDateTime: (ticks >> 32) as u32,
+                dwLowDateTime: (ticks & 0xFFFFFFFF) as u32,
+            }
+        });
+        let ptr = ft.as_ref().map_or_else(std::ptr::null, |ft| ft as *const _);
+        unsafe { SetThreadpoolWait(self.0, handle, ptr) };
     }
 
     /// The wait object will cease to queue new callbacks. Callbacks already queued will still fire
@@ -246,20 +396,32 @@ impl OwnedWaitHandle {
     }
 }
 
+/// Map the result of a threadpool wait callback. Results which are not documented are logged
+/// rather than mistaken for an abandoned mutex
+fn wait_result(waitresult: u32) -> WaitResult {
+    match waitresult {
+        WAIT_OBJECT_0 => Ok(()),
+        WAIT_TIMEOUT => Err(WaitError::Timeout),
+        WAIT_ABANDONED_0 => Err(WaitError::Abandoned),
+        unexpected => {
+            error!(unexpected, "unexpected wait result");
+            Err(WaitError::Unexpected(unexpected))
+        }
+    }
+}
+
 unsafe extern "system" fn wait_callback(
     _instance: PTP_CALLBACK_INSTANCE,
     context: *mut c_void,
     _wait: PTP_WAIT,
     waitresult: u32,
 ) {
-    let state = &*(context as *const Mutex<Shared>);
-    let mut shared = state.lock();
-    shared.result = match waitresult {
-        WAIT_OBJECT_0 => Some(Ok(())),
-        WAIT_TIMEOUT => Some(Err(WaitError::Timeout)),
-        _ => panic!("Unsupported kernel argument passed to wait callback!"),
-    };
-    if let Some(waker) = shared.waker.as_ref() {
-        waker.wake_by_ref()
-    }
+    abort_on_unwind("wait_callback", || {
+        let state = &*(context as *const Mutex<Shared>);
+        let mut shared = state.lock();
+        shared.result = Some(wait_result(waitresult));
+        if let Some(waker) = shared.waker.as_ref() {
+            waker.wake_by_ref()
+        }
+    })
 }
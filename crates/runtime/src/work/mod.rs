@@ -7,7 +7,11 @@
 //! https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-submitthreadpoolwork
 //! https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-waitforthreadpoolworkcallbacks
 
-use crate::common::{ThreadpoolCallbackEnvironment, ThreadpoolCallbackInstance, WaitPending};
+use crate::common::{
+    abort_on_unwind, ThreadpoolCallbackEnvironment, ThreadpoolCallbackInstance,
+    ThreadpoolCleanupGroup, WaitPending,
+};
+use crossbeam::queue::ArrayQueue;
 use parking_lot::Mutex;
 use std::{
     cell::UnsafeCell,
@@ -47,19 +51,44 @@ where
     }
 }
 
+/// A WorkFn may be called many times by a threadpool work
+pub trait WorkFn {
+    type Output;
+    fn work(&self, instance: ThreadpoolCallbackInstance) -> Self::Output;
+}
+
+impl<F, O> WorkFn for F
+where
+    F: Fn(ThreadpoolCallbackInstance) -> O,
+{
+    type Output = O;
+    fn work(&self, instance: ThreadpoolCallbackInstance) -> Self::Output {
+        (self)(instance)
+    }
+}
+
+/// Callback for which to register work handlers
+type WorkCallback = unsafe extern "system" fn(PTP_CALLBACK_INSTANCE, *mut c_void, PTP_WORK);
+
 struct OwnedWorkHandle(PTP_WORK);
 impl OwnedWorkHandle {
+    /// Release ownership of the handle without closing it, IE: when a cleanup group closes it
+    fn into_raw(self) -> PTP_WORK {
+        std::mem::ManuallyDrop::new(self).0
+    }
+
     /// Create a new threadpool handle
     ///
     /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-createthreadpoolwork
     ///
     /// Safety: env must point to an initialized ThreadpoolCallbackEnvironment, context must point
-    /// to initialized data and live as long as the handle
-    unsafe fn new<W>(env: *const ThreadpoolCallbackEnvironment, cx: *mut c_void) -> io::Result<Self>
-    where
-        W: WorkOnceFn,
-    {
-        let handle = CreateThreadpoolWork(Some(work_once_callback::<W>), cx, env as _);
+    /// to initialized data and live as long as the handle. The callback must expect the context
+    unsafe fn new(
+        env: *const ThreadpoolCallbackEnvironment,
+        cx: *mut c_void,
+        callback: WorkCallback,
+    ) -> io::Result<Self> {
+        let handle = CreateThreadpoolWork(Some(callback), cx, env as _);
         match handle {
             0 => Err(io::Error::last_os_error()),
             _ => Ok(Self(handle)),
@@ -148,7 +177,7 @@ where
         work: W,
     ) -> io::Result<Self> {
         let worker = Arc::new(Oneshot::new(work));
-        let handle = OwnedWorkHandle::new::<W>(env, Arc::as_ptr(&worker) as _)?;
+        let handle = OwnedWorkHandle::new(env, Arc::as_ptr(&worker) as _, work_once_callback::<W>)?;
         Ok(Self { handle, worker })
     }
 
@@ -161,6 +190,26 @@ where
             worker: self.worker,
         }
     }
+
+    /// Hand the work to the cleanup group it was created in. See
+    /// [`WorkOncePoolGuard::into_group_member`]
+    pub fn into_group_member(self, group: &ThreadpoolCleanupGroup)
+    where
+        W: 'static,
+    {
+        // NOTE the group closes the handle
+        self.handle.into_raw();
+        group.hold(self.worker);
+    }
+}
+
+/// The outcome of cancelling work with [`WorkOncePoolGuard::cancel_with`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorkCancel {
+    /// The work was cancelled before it ran and the future resolves with the cancel result
+    Cancelled,
+    /// The work ran before it could be cancelled and the future resolves with the work result
+    AlreadyComplete,
 }
 
 pub struct WorkOncePoolGuard<W>
@@ -183,17 +232,23 @@ where
     }
 
     /// Cancel any pending callbacks, wait for current callbacks to finish, and resolve the future
-    pub fn cancel_with(&self, result: W::Output) -> &Self {
+    /// with the result. If the work already ran, the genuine result is kept and the provided
+    /// result is dropped
+    pub fn cancel_with(&self, result: W::Output) -> WorkCancel {
         self.wait(WaitPending::Cancel);
-        // Safety: We ensure all callbacks have finished. We now have exclusive access
-        // NOTE we do not need to take the inner worker but it is nice sanity check
-        let _inner = unsafe { self.worker.try_take() };
-        let mut state = self.worker.state.lock();
-        state.result = Some(result);
-        if let Some(waker) = state.waker.take() {
-            waker.wake()
+        // Safety: We ensure all callbacks have finished. We now have exclusive access. If the
+        // inner worker was already taken then the callback ran and produced a result
+        match unsafe { self.worker.try_take() } {
+            None => WorkCancel::AlreadyComplete,
+            Some(_inner) => {
+                let mut state = self.worker.state.lock();
+                state.result = Some(result);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake()
+                }
+                WorkCancel::Cancelled
+            }
         }
-        self
     }
 
     pub fn future(&self) -> WorkOnceFuture<W> {
@@ -201,6 +256,70 @@ where
             worker: Arc::clone(&self.worker),
         }
     }
+
+    /// Hand the work to the cleanup group it was created in. The work is closed when the members
+    /// of the group are closed, see [`ThreadpoolCleanupGroup::close_members`]. Futures of work
+    /// which is cancelled by the group will never resolve
+    ///
+    /// NOTE the pool must have been created with an environment associated with this group
+    pub fn into_group_member(self, group: &ThreadpoolCleanupGroup)
+    where
+        W: 'static,
+    {
+        // NOTE the group closes the handle
+        self.handle.into_raw();
+        group.hold(self.worker);
+    }
+}
+
+/// A collection of work submitted to the same threadpool. Each work runs once and the futures
+/// may be collected with [`futures::future::join_all`]. To submit different closures to the same
+/// set, box them as `Box<dyn FnOnce(ThreadpoolCallbackInstance) -> O>`.
+///
+/// The set borrows the callback environment, so the environment (and its threadpool) must outlive
+/// every work in the set. When the set drops, pending work is cancelled and running work is
+/// waited on. Futures of cancelled work will never resolve, so keep the set alive while awaiting
+/// its futures.
+pub struct WorkSet<'env, W>
+where
+    W: WorkOnceFn,
+{
+    env: &'env ThreadpoolCallbackEnvironment,
+    guards: Vec<WorkOncePoolGuard<W>>,
+}
+
+impl<'env, W> WorkSet<'env, W>
+where
+    W: WorkOnceFn,
+{
+    /// Create an empty set of work which will be submitted to the environment
+    pub fn new(env: &'env ThreadpoolCallbackEnvironment) -> Self {
+        Self {
+            env,
+            guards: Vec::new(),
+        }
+    }
+
+    /// Submit work to the threadpool of this set
+    pub fn submit(&mut self, work: W) -> io::Result<&mut Self> {
+        let pool = WorkOncePool::with_environment(self.env, work)?;
+        self.guards.push(pool.submit_once());
+        Ok(self)
+    }
+
+    /// The futures of all work in this set, in the order the work was submitted
+    pub fn futures(&self) -> Vec<WorkOnceFuture<W>> {
+        self.guards.iter().map(WorkOncePoolGuard::future).collect()
+    }
+
+    /// Wait for all work in this set to finish. Additionally, you may specify to cancel any
+    /// pending callbacks
+    pub fn wait(&self, cancel: WaitPending) -> &Self {
+        for guard in &self.guards {
+            guard.wait(cancel);
+        }
+        self
+    }
 }
 
 pub struct WorkOnceFuture<W>
@@ -216,24 +335,29 @@ where
 {
     type Output = W::Output;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut lock = self.worker.state.lock();
-        match lock.result.take() {
-            Some(result) => Poll::Ready(result),
-            None => {
-                // Some waker accounting
-                let new_waker = cx.waker();
-                lock.waker = match lock.waker.take() {
-                    None => Some(new_waker.clone()),
-                    Some(old_waker) => {
-                        if old_waker.will_wake(new_waker) {
-                            Some(old_waker)
-                        } else {
-                            Some(new_waker.clone())
-                        }
+        poll_shared(&self.worker.state, cx)
+    }
+}
+
+/// Take the result from the shared state or register a waker to be woken when the result is ready
+fn poll_shared<O>(state: &Mutex<Shared<O>>, cx: &mut Context<'_>) -> Poll<O> {
+    let mut lock = state.lock();
+    match lock.result.take() {
+        Some(result) => Poll::Ready(result),
+        None => {
+            // Some waker accounting
+            let new_waker = cx.waker();
+            lock.waker = match lock.waker.take() {
+                None => Some(new_waker.clone()),
+                Some(old_waker) => {
+                    if old_waker.will_wake(new_waker) {
+                        Some(old_waker)
+                    } else {
+                        Some(new_waker.clone())
                     }
-                };
-                Poll::Pending
-            }
+                }
+            };
+            Poll::Pending
         }
     }
 }
@@ -304,16 +428,174 @@ pub unsafe extern "system" fn work_once_callback<W>(
 ) where
     W: WorkOnceFn,
 {
-    // Safety: instance is a raw handle to a PTP_CALLBACK_INSTANCE
-    let i = unsafe { ThreadpoolCallbackInstance::from_raw_handle(instance as _) };
-    let cx = &*(context as *const Oneshot<W>);
-    // Safety: We guarentee exclusive access to the inner because only we are allowed to call the
-    // take method.  The WorkOnceFuture must not reference the inner worker.
-    let inner = unsafe { cx.take() };
-    let result = inner.work_once(i);
-    let mut lock = cx.state.lock();
+    // NOTE the worker is user code which may panic
+    abort_on_unwind("work_once_callback", || {
+        // Safety: instance is a raw handle to a PTP_CALLBACK_INSTANCE
+        let i = unsafe { ThreadpoolCallbackInstance::from_raw_handle(instance as _) };
+        let cx = &*(context as *const Oneshot<W>);
+        // Safety: We guarentee exclusive access to the inner because only we are allowed to call
+        // the take method.  The WorkOnceFuture must not reference the inner worker.
+        let inner = unsafe { cx.take() };
+        let result = inner.work_once(i);
+        let mut lock = cx.state.lock();
+        lock.result = Some(result);
+        if let Some(waker) = lock.waker.take() {
+            waker.wake()
+        }
+    })
+}
+
+/// The shared state of a single submission to a [`WorkPool`]. The result is None when the
+/// submission was cancelled before the callback ran
+type Submission<O> = Arc<Mutex<Shared<Option<O>>>>;
+
+/// Resolve a submission and wake the future waiting for the result
+fn resolve<O>(submission: &Submission<O>, result: Option<O>) {
+    let mut lock = submission.lock();
     lock.result = Some(result);
     if let Some(waker) = lock.waker.take() {
         waker.wake()
     }
 }
+
+/// Shared state between the work callbacks and the pool. Every submission pushes a pending
+/// submission before submitting work, so every callback has exactly one submission to resolve
+struct Repeat<W: WorkFn> {
+    inner: W,
+    pending: ArrayQueue<Submission<W::Output>>,
+}
+
+/// An owned handle to a Threadpoolwork which may be submitted many times. Unlike the
+/// [`WorkOncePool`], every submission returns a [`WorkFuture`] which resolves with the result of
+/// that submission.
+///
+/// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-createthreadpoolwork
+///
+/// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857). The handle must drop and wait for callbacks
+/// before the worker is freed
+pub struct WorkPool<W>
+where
+    W: WorkFn,
+{
+    /// A handle to the underlying Worker
+    handle: OwnedWorkHandle,
+    /// The worker callback and the pending submissions. Callbacks reference the worker
+    /// concurrently so the worker must be Sync
+    worker: Arc<Repeat<W>>,
+}
+
+impl<W> WorkPool<W>
+where
+    W: WorkFn + Sync,
+    W::Output: Send,
+{
+    /// Construct a new ThreadpoolWork handle which allows at most `capacity` submissions to be in
+    /// flight at once.
+    ///
+    /// Panics if the capacity is zero
+    ///
+    /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-createthreadpoolwork
+    pub fn new(capacity: usize, work: W) -> io::Result<Self> {
+        // Safety: A null pointer is allowed
+        unsafe { Self::with_environment_raw(std::ptr::null(), capacity, work) }
+    }
+
+    /// Construct a new threadpool work handle with a private threadpool
+    pub fn with_environment(
+        env: &ThreadpoolCallbackEnvironment,
+        capacity: usize,
+        work: W,
+    ) -> io::Result<Self> {
+        // Safety: A reference to a threadpool callback environment is already initialized
+        unsafe { Self::with_environment_raw(env as *const _, capacity, work) }
+    }
+
+    /// Construct a new threadpool work handle with a private threadpool
+    ///
+    /// Safety: raw pointer must be a pointer to an initialized ThreadpoolCallbackEnvironment or a
+    /// NULL Pointer
+    pub unsafe fn with_environment_raw(
+        env: *const ThreadpoolCallbackEnvironment,
+        capacity: usize,
+        work: W,
+    ) -> io::Result<Self> {
+        let worker = Arc::new(Repeat {
+            inner: work,
+            pending: ArrayQueue::new(capacity),
+        });
+        let handle = OwnedWorkHandle::new(env, Arc::as_ptr(&worker) as _, work_callback::<W>)?;
+        Ok(Self { handle, worker })
+    }
+
+    /// Submit work to the threadpool worker pool. Returns None when the number of submissions in
+    /// flight has reached the capacity of the pool
+    pub fn submit(&self) -> Option<WorkFuture<W::Output>> {
+        let submission = Arc::new(Mutex::new(Shared {
+            waker: None,
+            result: None,
+        }));
+        self.worker.pending.push(Arc::clone(&submission)).ok()?;
+        self.handle.submit();
+        Some(WorkFuture { submission })
+    }
+
+    /// Wait for the submitted work to finish. Additionally, you may specify to cancel any pending
+    /// callbacks that have been submitted to the threadpool work pool. Cancelled submissions
+    /// resolve with None
+    pub fn wait(&self, cancel: WaitPending) -> &Self {
+        self.handle.wait(cancel);
+        if cancel == WaitPending::Cancel {
+            while let Some(submission) = self.worker.pending.pop() {
+                resolve(&submission, None);
+            }
+        }
+        self
+    }
+}
+
+impl<W> Drop for WorkPool<W>
+where
+    W: WorkFn,
+{
+    fn drop(&mut self) {
+        // Wait for all in flight callbacks before the shared context is freed, and resolve the
+        // futures of any submissions which never ran
+        self.handle.wait(WaitPending::Cancel);
+        while let Some(submission) = self.worker.pending.pop() {
+            resolve(&submission, None);
+        }
+    }
+}
+
+/// A future which resolves with the result of a single submission to a [`WorkPool`]. Resolves
+/// with None if the submission was cancelled before the work ran
+pub struct WorkFuture<O> {
+    submission: Submission<O>,
+}
+
+impl<O> Future for WorkFuture<O> {
+    type Output = Option<O>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_shared(&self.submission, cx)
+    }
+}
+
+/// We run the callers callback, once per submission
+pub unsafe extern "system" fn work_callback<W>(
+    instance: PTP_CALLBACK_INSTANCE,
+    context: *mut c_void,
+    _work: PTP_WORK,
+) where
+    W: WorkFn,
+{
+    // NOTE the worker is user code which may panic
+    abort_on_unwind("work_callback", || {
+        // Safety: instance is a raw handle to a PTP_CALLBACK_INSTANCE
+        let i = unsafe { ThreadpoolCallbackInstance::from_raw_handle(instance as _) };
+        let cx = &*(context as *const Repeat<W>);
+        let result = cx.inner.work(i);
+        if let Some(submission) = cx.pending.pop() {
+            resolve(&submission, Some(result));
+        }
+    })
+}
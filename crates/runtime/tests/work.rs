@@ -6,3 +6,38 @@ fn threadpool_test_work() {
     let result = futures::executor::block_on(pool.future());
     assert_eq!(42, result)
 }
+
+#[test]
+fn threadpool_test_work_pool() {
+    let pool = msft_runtime::work::WorkPool::new(3, |_| 42).unwrap();
+    let futures = (0..3).map(|_| pool.submit().unwrap()).collect::<Vec<_>>();
+    let results = futures::executor::block_on(futures::future::join_all(futures));
+    assert_eq!(vec![Some(42); 3], results);
+
+    // Capacity is released once the submissions complete
+    let result = futures::executor::block_on(pool.submit().unwrap());
+    assert_eq!(Some(42), result);
+}
+
+#[test]
+fn threadpool_test_work_cancel_after_complete() {
+    use msft_runtime::{common::WaitPending, work::WorkCancel};
+    let pool = msft_runtime::work::once(|_| 42).unwrap();
+    pool.wait(WaitPending::Wait);
+    assert_eq!(WorkCancel::AlreadyComplete, pool.cancel_with(0));
+    let result = futures::executor::block_on(pool.future());
+    assert_eq!(42, result)
+}
+
+#[test]
+fn threadpool_test_work_set() {
+    use msft_runtime::common::{ThreadpoolCallbackEnvironment, ThreadpoolCallbackInstance};
+    let env = ThreadpoolCallbackEnvironment::new();
+    let mut set = msft_runtime::work::WorkSet::new(&env);
+    for n in 0..4 {
+        let work: Box<dyn FnOnce(ThreadpoolCallbackInstance) -> usize> = Box::new(move |_| n * 2);
+        set.submit(work).unwrap();
+    }
+    let results = futures::executor::block_on(futures::future::join_all(set.futures()));
+    assert_eq!(vec![0, 2, 4, 6], results);
+}
@@ -5,8 +5,8 @@ use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::{format_ident, quote, ToTokens};
 use syn::{
     parse::Parser, punctuated::Punctuated, Attribute, Error, Expr, ExprLit, ExprPath, ExprTuple,
-    FnArg, ItemFn, Lit, LitInt, LitStr, MetaNameValue, Pat, PatType, Path, Result, Token, Type,
-    TypePath,
+    FnArg, Ident, ItemFn, Lit, LitBool, LitInt, LitStr, MetaNameValue, Pat, PatType, Path, Result,
+    Token, Type, TypePath,
 };
 
 /// For collecting the service arguments
@@ -20,7 +20,15 @@ struct Service {
 struct Meta {
     name: Option<LitStr>,
     worker_threads: Option<LitInt>,
-    mt: bool,
+    queue_capacity: Option<LitInt>,
+    /// A static `OnceLock<T>` initialized by the caller before starting the service dispatcher.
+    /// The service body receives a `context: &T` binding
+    context: Option<Path>,
+    /// The async runtime used to drive an async service routine. Either "tokio" (the default) or
+    /// "none", which drives the routine with `futures::executor::block_on`. The none runtime has
+    /// no worker threads, so `mt` is an error and `worker_threads` only sizes the threadpool
+    runtime: Option<LitStr>,
+    mt: Option<LitBool>,
 }
 
 /// A general message displayed at the callsite when the user supplied invalid tuple
@@ -79,7 +87,7 @@ fn match_mt(mut meta: Meta, expr: Expr) -> Meta {
         Expr::Lit(ExprLit {
             lit: Lit::Bool(b), ..
         }) => {
-            meta.mt = b.value;
+            meta.mt = Some(b);
             meta
         }
         _ => meta,
@@ -98,11 +106,48 @@ fn match_worker_threads(mut meta: Meta, expr: Expr) -> Meta {
     }
 }
 
+fn match_queue_capacity(mut meta: Meta, expr: Expr) -> Meta {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(i), ..
+        }) => {
+            meta.queue_capacity = Some(i);
+            meta
+        }
+        _ => meta,
+    }
+}
+
+fn match_context(mut meta: Meta, expr: Expr) -> Meta {
+    match expr {
+        Expr::Path(ExprPath { path, .. }) => {
+            meta.context = Some(path);
+            meta
+        }
+        _ => meta,
+    }
+}
+
+fn match_runtime(mut meta: Meta, expr: Expr) -> Meta {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(s), ..
+        }) => {
+            meta.runtime = Some(s);
+            meta
+        }
+        _ => meta,
+    }
+}
+
 fn fold_meta(meta: Meta, expr: MetaNameValue) -> Meta {
     match expr.path.get_ident() {
         Some(ident) if ident == "name" => match_name(meta, expr.value),
         Some(ident) if ident == "mt" => match_mt(meta, expr.value),
         Some(ident) if ident == "worker_threads" => match_worker_threads(meta, expr.value),
+        Some(ident) if ident == "queue_capacity" => match_queue_capacity(meta, expr.value),
+        Some(ident) if ident == "context" => match_context(meta, expr.value),
+        Some(ident) if ident == "runtime" => match_runtime(meta, expr.value),
         _ => meta,
     }
 }
@@ -133,6 +178,22 @@ fn find_arg<'a>(name: &'static str, func: &'a ItemFn) -> Result<(&'a Pat, &'a Pa
         .ok_or_else(|| err_missing_arg(name, func.clone()))
 }
 
+/// The constant name for a service name. IE: "My Service" becomes SERVICE_MY_SERVICE
+fn service_ident(name: &LitStr) -> Result<Ident> {
+    let value = name.value();
+    if value.trim().is_empty() {
+        return Err(Error::new_spanned(name, "Service name must not be empty"));
+    }
+    let sanitized = value
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    Ok(format_ident!("SERVICE_{}", sanitized))
+}
+
 pub fn expand_start_service_ctrl_dispatcher(toks: TokenStream) -> Result<TokenStream2> {
     let parsed = Parser::parse(Punctuated::<ExprTuple, Token![,]>::parse_terminated, toks)?;
     let nservices = parsed.iter().len();
@@ -142,10 +203,27 @@ pub fn expand_start_service_ctrl_dispatcher(toks: TokenStream) -> Result<TokenSt
         .into_iter()
         .try_fold(Vec::with_capacity(nservices), fold)?;
 
+    // Generate an identifier for each service name, and make sure they are unique
+    let idents = folded.iter().try_fold(
+        Vec::<Ident>::with_capacity(nservices),
+        |mut idents, service| {
+            let ident = service_ident(&service.name)?;
+            match idents.contains(&ident) {
+                true => Err(Error::new_spanned(
+                    &service.name,
+                    format!("Service name collides with another service name as {ident}"),
+                )),
+                false => {
+                    idents.push(ident);
+                    Ok(idents)
+                }
+            }
+        },
+    )?;
+
     // Generate *const u16 namse for service array
-    let names = folded.iter().map(|service| {
+    let names = folded.iter().zip(idents.iter()).map(|(service, arg)| {
         let name = &service.name;
-        let arg = format_ident!("SERVICE_{}", name.value().to_uppercase().replace(" ", "_"));
         let attrs = service.attrs.iter();
         quote! {
             #(#attrs)*
@@ -154,9 +232,7 @@ pub fn expand_start_service_ctrl_dispatcher(toks: TokenStream) -> Result<TokenSt
     });
 
     // Generate SERVICE_TABLE_ENTRYW array items
-    let table_items = folded.iter().map(|service| {
-        let name = &service.name;
-        let arg = format_ident!("SERVICE_{}", name.value().to_uppercase().replace(" ", "_"));
+    let table_items = folded.iter().zip(idents.iter()).map(|(service, arg)| {
         let func = &service.service;
         quote! {
             windows_sys::Win32::System::Services::SERVICE_TABLE_ENTRYW {
@@ -200,6 +276,9 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
         name,
         mt,
         worker_threads,
+        queue_capacity,
+        context,
+        runtime,
     } = Parser::parse(
         Punctuated::<MetaNameValue, Token![,]>::parse_terminated,
         attrs,
@@ -207,6 +286,17 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
     .into_iter()
     .fold(Meta::default(), fold_meta);
 
+    // Only tokio and none are supported runtimes
+    let tokio = match runtime {
+        Some(runtime) if runtime.value() == "none" => Ok(false),
+        Some(runtime) if runtime.value() == "tokio" => Ok(true),
+        Some(runtime) => Err(Error::new_spanned(
+            runtime,
+            "Expected runtime = \"tokio\" or runtime = \"none\"",
+        )),
+        None => Ok(true),
+    }?;
+
     // Parse the original function
     let orig = syn::parse::<ItemFn>(toks)?;
     // NOTE we're not sure if our service Arguments are in scope or not, so we reuse the callers
@@ -217,6 +307,21 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
     let (stream_pat, stream_path) = find_arg("ServiceMessageStream", &orig)?;
     let (status_handle_pat, status_handle_path) = find_arg("StatusHandle", &orig)?;
 
+    // The none runtime has no worker threads, so the tokio options would be silently ignored.
+    // NOTE worker_threads still sizes the threadpool when the caller accepts an environment
+    if !tokio {
+        if let Some(mt) = &mt {
+            return Err(Error::new_spanned(mt, "mt requires runtime = \"tokio\""));
+        }
+        let threadpool = find_arg("ThreadpoolCallbackEnvironment", &orig).is_ok();
+        if let (Some(threads), false) = (&worker_threads, threadpool) {
+            return Err(Error::new_spanned(
+                threads,
+                "worker_threads requires runtime = \"tokio\" or a ThreadpoolCallbackEnvironment",
+            ));
+        }
+    }
+
     // We construct the service handle, Vec<OsString>, and a stream of SCM messages. Note that the
     // names __dwnumserviceargs and __lpserviceargvectors must match the final construction of the
     // fn arguments
@@ -229,66 +334,165 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
         }
     })?;
 
-    // Create a stream which will be registered with the status handle
-    let init_stream = quote! {
-        let #stream_pat: #stream_path = Default::default();
+    // Create a stream which will be registered with the status handle. When the caller supplied a
+    // queue capacity the stream buffers a bounded number of messages
+    let init_stream = match queue_capacity {
+        Some(capacity) => quote! {
+            let #stream_pat: #stream_path = #stream_path::with_capacity(#capacity);
+        },
+        None => quote! {
+            let #stream_pat: #stream_path = Default::default();
+        },
     };
 
-    // Create a status handle and register the stream.
-    // return an Err(type)
+    // Create a status handle and register the stream. If we fail to register a status handle
+    // there is no way to report a status to the SCM, so we log the error and return. The SCM will
+    // notice the service failed to start and apply the configured recovery actions.
     let init_handle = quote! {
-        let #status_handle_pat = match #status_handle_path::new(
+        #[allow(unused_mut)]
+        let mut __status_handle = match #status_handle_path::new(
             SERVICE_NAME,
             &#stream_pat) {
             Ok(handle) => handle,
             Err(error) => {
                 tracing::error!("Failed to register status handle {:?}", error);
-                panic!("Failed to register status handle {:?}", error);
+                return;
             }
         };
     };
 
+    // Hand the status handle to the callers service routine
+    let bind_handle = quote! {
+        let #status_handle_pat = __status_handle;
+    };
+
+    // Keep a copy of the status handle to report the service routine result after the callers
+    // service routine returns
+    let keep_handle = quote! {
+        let __status_exit = __status_handle.clone();
+    };
+
     // Initialize a string for registering the ServiceStatusHandle
     let init_service_name = quote! {
         const SERVICE_NAME: *const u16 = windows_sys::w!(#name);
     };
 
-    let rt = if mt {
-        // TODO get the number of threads via runtime... ie windows_sys::Info....
-        let nworkers = worker_threads
-            .map(|int| int.into_token_stream())
-            .unwrap_or_else(|| {
-                quote! {{
-                    use windows_sys::Win32::System::SystemInformation::{SYSTEM_INFO, GetSystemInfo};
-                    let mut info = std::mem::zeroed::<SYSTEM_INFO>();
-                    unsafe { GetSystemInfo(&mut info as _) };
-                    info.dwNumberOfProcessors << 1
-                }}
-            });
+    // TODO get the number of threads via runtime... ie windows_sys::Info....
+    let nworkers = worker_threads
+        .map(|int| int.into_token_stream())
+        .unwrap_or_else(|| {
+            quote! {{
+                use windows_sys::Win32::System::SystemInformation::{SYSTEM_INFO, GetSystemInfo};
+                let mut info = std::mem::zeroed::<SYSTEM_INFO>();
+                unsafe { GetSystemInfo(&mut info as _) };
+                info.dwNumberOfProcessors << 1
+            }}
+        });
+
+    let rt = if matches!(&mt, Some(mt) if mt.value) {
         quote! {{
             let nworkers: u32 = #nworkers as _;
-            match tokio::runtime::Builder::new_multi_thread()
+            tokio::runtime::Builder::new_multi_thread()
                 .worker_threads(nworkers as _)
-                .build() {
-                    Ok(rt) => rt,
-                    Err(e) => {
-                        tracing::error!("Failed to build tokio runtime {:?}", e);
-                        panic!("Failed to build tokio runtime {:?}", e);
-                    }
-                }
+                .build()
         }}
     } else {
         quote! {
-            match tokio::runtime::Builder::new_current_thread().build() {
+            tokio::runtime::Builder::new_current_thread().build()
+        }
+    };
+
+    // Report the service as stopped so the SCM can apply the configured recovery actions. The
+    // service specific exit code is only meaningful with ERROR_SERVICE_SPECIFIC_ERROR, and tells
+    // which step failed
+    let report_stopped = |exit_code: TokenStream2, failure: &str| {
+        let failure = format_ident!("{}", failure);
+        quote! {
+            let _ = match #exit_code {
+                windows_sys::Win32::Foundation::ERROR_SERVICE_SPECIFIC_ERROR => __status_handle
+                    .report_failed(msft_service::status::ServiceFailure::#failure as u32),
+                exit_code => __status_handle.report_stopped(exit_code),
+            };
+        }
+    };
+
+    // Report the win32 error code when there is one
+    let os_exit_code = quote! {
+        error
+            .raw_os_error()
+            .map(|code| code as u32)
+            .unwrap_or(windows_sys::Win32::Foundation::ERROR_SERVICE_SPECIFIC_ERROR)
+    };
+
+    // When we fail to build a runtime we report the service as stopped
+    let report_rt_stopped = report_stopped(os_exit_code.clone(), "Runtime");
+    let init_rt = tokio.then(|| {
+        quote! {
+            let runtime = match #rt {
                 Ok(rt) => rt,
-                Err(e) => {
-                    tracing::error!("Failed to build tokio runtime {:?}", e);
-                    panic!("Failed to build tokio runtime {:?}", e);
+                Err(error) => {
+                    tracing::error!("Failed to build tokio runtime {:?}", error);
+                    #report_rt_stopped
+                    return;
                 }
-            }
+            };
+        }
+    });
+
+    // Drive the callers async service routine on the tokio runtime, or block the ServiceMain
+    // thread with a simple executor when the caller opted out of tokio
+    let block_on = match tokio {
+        true => quote! { runtime.block_on },
+        false => quote! { msft_service::futures::executor::block_on },
+    };
+
+    // When the callers service routine returns an error we report the service as stopped
+    let report_exit_stopped = report_stopped(quote! { exit_code }, "Routine");
+
+    // When the caller accepts a ThreadpoolCallbackEnvironment we create a private threadpool sized
+    // to the worker threads. NOTE the threadpool is declared before the tokio runtime so that it
+    // is dropped after the runtime, and outlives every pool created from the environment.
+    let init_threadpool = match find_arg("ThreadpoolCallbackEnvironment", &orig) {
+        Ok((env_pat, env_path)) => {
+            let report_threadpool_stopped = report_stopped(os_exit_code, "Threadpool");
+            Some(quote! {
+                let __threadpool = match msft_service::runtime::common::ThreadpoolHandle::new()
+                    .and_then(|pool| {
+                        pool.min_threads(1)?.max_threads(#nworkers as _);
+                        Ok(pool)
+                    }) {
+                    Ok(pool) => pool,
+                    Err(error) => {
+                        tracing::error!("Failed to create threadpool {:?}", error);
+                        #report_threadpool_stopped
+                        return;
+                    }
+                };
+                let #env_pat: #env_path = __threadpool.new_environment();
+            })
         }
+        Err(_) => None,
     };
 
+    // Bind the shared context into the service body. The caller must initialize the context
+    // before starting the service dispatcher, if they did not we report the service as stopped
+    let init_context = context.map(|path| {
+        let report_context_stopped = report_stopped(
+            quote! { windows_sys::Win32::Foundation::ERROR_SERVICE_SPECIFIC_ERROR },
+            "Context",
+        );
+        quote! {
+            let context = match #path.get() {
+                Some(context) => context,
+                None => {
+                    tracing::error!("Service context {} was not initialized", stringify!(#path));
+                    #report_context_stopped
+                    return;
+                }
+            };
+        }
+    });
+
     // Get parts of the original function (visibility, name, block statements). For to reconstruct
     // a new function
     let vis = &orig.vis;
@@ -298,18 +502,33 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
     // If we are async we consruct a tokio runtime and run the users statements in an async block.
     // If we are not async then we simply render the statements. The caller is expected to setup
     // the runtime to setup the SCM message handling because the SCM messages are a stream!
+    //
+    // NOTE the service main is called by the SCM dispatcher, so a panic in the users statements
+    // must not unwind into windows
     if orig.sig.asyncness.is_some() {
         Ok(quote! {
             #vis unsafe extern "system" fn #fn_name_orig (
                 __dwnumserviceargs: u32,
                 __lpserviceargvectors: *mut *mut u16) {
-                #init_service_name
-                #init_os_service_args
-                #init_stream
-                #init_handle
-                let runtime = #rt.block_on(async move {
-                    #(#stmts)*
-                });
+                msft_service::runtime::common::abort_on_unwind(stringify!(#fn_name_orig), || {
+                    #init_service_name
+                    #init_os_service_args
+                    #init_stream
+                    #init_handle
+                    #init_context
+                    #init_threadpool
+                    #init_rt
+                    #keep_handle
+                    #bind_handle
+                    let output = #block_on(async move {
+                        #(#stmts)*
+                    });
+                    if let Some(exit_code) = msft_service::status::ServiceExit::exit_code(output) {
+                        #[allow(unused_mut)]
+                        let mut __status_handle = __status_exit;
+                        #report_exit_stopped
+                    }
+                })
             }
         })
     } else {
@@ -317,11 +536,16 @@ pub fn expand_service(attrs: TokenStream, toks: TokenStream) -> Result<TokenStre
             #vis unsafe extern "system" fn #fn_name_orig (
                 __dwnumserviceargs: u32,
                 __lpserviceargvectors: *mut *mut u16) {
-                #init_service_name
-                #init_os_service_args
-                #init_stream
-                #init_handle
-                #(#stmts)*
+                msft_service::runtime::common::abort_on_unwind(stringify!(#fn_name_orig), || {
+                    #init_service_name
+                    #init_os_service_args
+                    #init_stream
+                    #init_handle
+                    #init_context
+                    #init_threadpool
+                    #bind_handle
+                    #(#stmts)*
+                })
             }
         })
     }
@@ -43,7 +43,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .listen()
         .take_until(abort)
         .filter_map(|ev| future::ready(plug_events(ev)))
-        .track(vec![("2FE3", "0100")])?;
+        .track(vec![("2FE3", "0100")])?
+        .skip_scan_errors();
 
     // Spawn a task to listen for USB plug/unplug events
     let jh: JoinHandle<Result<(), TrackingError>> = tokio::spawn(async move {
@@ -10,6 +10,10 @@ use crate::{
     guid,
     message::DeviceEvent,
     message::{DeviceEventData, DeviceEventType},
+    runtime::{
+        common::abort_on_unwind,
+        timer::{TimerPool, TimerThreadpoolOptions},
+    },
     status::StatusHandle,
     util::{
         hkey::{RegistryData, UnexpectedRegistryData},
@@ -18,13 +22,13 @@ use crate::{
     },
 };
 use crossbeam::queue::SegQueue;
-use futures::{ready, Future, Stream};
+use futures::{future::BoxFuture, ready, Future, FutureExt, Stream};
 use parking_lot::Mutex;
 use pin_project_lite::pin_project;
 use std::{
     borrow::Cow,
     cell::OnceCell,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ffi::OsString,
     fmt::{self, Formatter},
     io,
@@ -34,6 +38,7 @@ use std::{
     sync::Arc,
     task::{Context, Poll, Waker},
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 use tracing::{debug, error, trace, warn};
 use windows_sys::{
@@ -56,31 +61,36 @@ unsafe extern "system" fn device_notification_window_proceedure(
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
-    let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const DeviceNotificationData;
-    if !ptr.is_null() {
-        match msg {
-            // Safety: lparam is a DEV_BROADCAST_HDR when msg is WM_DEVICECHANGE
-            WM_DEVICECHANGE => match unsafe { DeviceEvent::try_parse(wparam as _, lparam as _) } {
-                Some(msg) => {
-                    debug!(?msg.ty);
-                    (&*ptr).try_wake_with(Some(msg));
+    abort_on_unwind("device_notification_window_proceedure", || {
+        let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const DeviceNotificationData;
+        if !ptr.is_null() {
+            match msg {
+                // Safety: lparam is a DEV_BROADCAST_HDR when msg is WM_DEVICECHANGE
+                WM_DEVICECHANGE => {
+                    match unsafe { DeviceEvent::try_parse(wparam as _, lparam as _) } {
+                        Some(msg) if (&*ptr).accepts(&msg) => {
+                            debug!(?msg.ty);
+                            (&*ptr).try_wake_with(Some(msg));
+                            0
+                        }
+                        Some(_) => 0,
+                        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+                    }
+                }
+                WM_DESTROY => {
+                    if let Ok(window) = crate::get_window_text!(hwnd, 128) {
+                        trace!(?window, "wm_destroy");
+                    }
+                    let arc = Arc::from_raw(ptr as *const DeviceNotificationData);
+                    arc.try_wake_with(None);
                     0
                 }
                 _ => DefWindowProcW(hwnd, msg, wparam, lparam),
-            },
-            WM_DESTROY => {
-                if let Ok(window) = crate::get_window_text!(hwnd, 128) {
-                    trace!(?window, "wm_destroy");
-                }
-                let arc = Arc::from_raw(ptr as *const DeviceNotificationData);
-                arc.try_wake_with(None);
-                0
             }
-            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        } else {
+            DefWindowProcW(hwnd, msg, wparam, lparam)
         }
-    } else {
-        DefWindowProcW(hwnd, msg, wparam, lparam)
-    }
+    })
 }
 
 /// Create an instance of a DeviceNotifier window.
@@ -212,15 +222,9 @@ fn get_window_class() -> u16 {
 /// Then will scan HARDWARE\\DEVICEMAP\\SERIALCOMM registry to get a list of currently connected
 /// devices.  Then we have all the information to provide a hashmap of currently connected USB COM
 /// ports including the Vendor/Product ID's.
-pub fn scan() -> Result<HashMap<OsString, UsbVidPid>, ScanError> {
+pub fn scan() -> Result<HashMap<OsString, UsbDevice>, ScanError> {
     // We collect all the currently connected COM ports from the registry
-    let connected = crate::util::hkey::open(
-        crate::util::hkey::PredefinedHkey::LOCAL_MACHINE,
-        "HARDWARE\\DEVICEMAP\\SERIALCOMM",
-    )?
-    .into_values()?
-    .map(|value| value?.1.try_into_os_string().map_err(ScanError::from))
-    .collect::<Result<Vec<OsString>, ScanError>>()?;
+    let connected = self::list_ports()?;
 
     // We collect all the vender and product id's from the registry
     let devices = crate::util::hkey::open(
@@ -230,9 +234,9 @@ pub fn scan() -> Result<HashMap<OsString, UsbVidPid>, ScanError> {
     .into_values()?
     .map(|value| {
         let (port, data) = value?;
-        UsbVidPid::try_from(data).map(|vidpid| (port, vidpid))
+        UsbDevice::try_from(data).map(|device| (port, device))
     })
-    .collect::<Result<HashMap<OsString, UsbVidPid>, ScanError>>()?;
+    .collect::<Result<HashMap<OsString, UsbDevice>, ScanError>>()?;
 
     // Filter the registry map to only list connected devices We loop again because we want to
     // properly capture errors
@@ -242,8 +246,23 @@ pub fn scan() -> Result<HashMap<OsString, UsbVidPid>, ScanError> {
         .collect())
 }
 
+/// List every connected COM port.
+///
+/// Unlike [`scan`], this routine only reads the HARDWARE\\DEVICEMAP\\SERIALCOMM registry, so
+/// built in serial ports and other ports without a Vendor/Product ID (IE: Bluetooth SPP) are
+/// included.
+pub fn list_ports() -> io::Result<Vec<OsString>> {
+    crate::util::hkey::open(
+        crate::util::hkey::PredefinedHkey::LOCAL_MACHINE,
+        "HARDWARE\\DEVICEMAP\\SERIALCOMM",
+    )?
+    .into_values()?
+    .map(|value| Ok(value?.1.try_into_os_string()?))
+    .collect::<io::Result<Vec<OsString>>>()
+}
+
 /// Scan all the connected usb devices, and return the ID's for a chosen port (if it exists)
-pub fn scan_for(port: &OsString) -> Result<UsbVidPid, ScanError> {
+pub fn scan_for(port: &OsString) -> Result<UsbDevice, ScanError> {
     trace!(?port, "scanning for usb device");
     self::scan()
         .map(|mut devices| devices.remove(port))?
@@ -298,11 +317,22 @@ impl TryFrom<RegistryData> for UsbVidPid {
         let data = os_str
             .to_str()
             .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "unsupported registry value"))?;
-        Self::try_from((&data[12..16], &data[21..25]))
+        Self::try_from((find_id(data, "VID_"), find_id(data, "PID_")))
             .map_err(|e| ScanError::InvalidRegistryData(e, os_str))
     }
 }
 
+/// Find the 4 hex characters following a prefix (IE: "VID_"), ignoring case. Drivers format the
+/// device path differently (IE: `usb#vid_2341&pid_0043` or `FTDIBUS#VID_0403+PID_6001`) so we
+/// search for the prefix rather than slice at a fixed offset. Returns an empty string when the
+/// prefix is missing so that parsing the ID fails with an error
+fn find_id<'a>(data: &'a str, prefix: &str) -> &'a str {
+    data.to_ascii_uppercase()
+        .find(prefix)
+        .and_then(|i| data.get(i + prefix.len()..i + prefix.len() + 4))
+        .unwrap_or("")
+}
+
 impl<'v, 'p, V, P> TryFrom<(V, P)> for UsbVidPid
 where
     V: Into<Cow<'v, str>>,
@@ -316,6 +346,113 @@ where
     }
 }
 
+/// A USB device listed in the COM Name Arbiter registry. The registry lists the device interface
+/// path of the device. IE: `\\?\usb#vid_2341&pid_0043#7523733353635111A1F2#{86e0d1e0-...}`
+#[derive(Clone, PartialEq)]
+pub struct UsbDevice {
+    ids: UsbVidPid,
+    serial: Option<OsString>,
+    instance_id: OsString,
+    interface_path: OsString,
+}
+
+impl UsbDevice {
+    /// The Vendor/Product ID's of the device
+    pub fn ids(&self) -> UsbVidPid {
+        self.ids
+    }
+
+    pub fn vid(&self) -> String {
+        self.ids.vid()
+    }
+
+    pub fn pid(&self) -> String {
+        self.ids.pid()
+    }
+
+    /// The serial number reported by the device. None if the device does not report a serial
+    /// number, in which case windows generates an instance suffix containing '&'
+    pub fn serial(&self) -> Option<&OsString> {
+        self.serial.as_ref()
+    }
+
+    /// The device instance ID. IE: `usb\vid_2341&pid_0043\7523733353635111A1F2`. Use the
+    /// instance ID to tell apart multiple devices with the same Vendor/Product ID's
+    pub fn instance_id(&self) -> &OsString {
+        &self.instance_id
+    }
+
+    /// The device interface path (symbolic link) as listed in the registry. IE:
+    /// `\\?\usb#vid_2341&pid_0043#7523733353635111A1F2#{86e0d1e0-...}`. Unlike the COM port
+    /// name the path is stable when the device is plugged back in
+    pub fn interface_path(&self) -> &OsString {
+        &self.interface_path
+    }
+
+    pub fn matches(&self, vid: &str, pid: &str) -> bool {
+        self.ids.matches(vid, pid)
+    }
+}
+
+impl fmt::Debug for UsbDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbDevice")
+            .field("vid", &self.vid())
+            .field("pid", &self.pid())
+            .field("serial", &self.serial)
+            .field("instance_id", &self.instance_id)
+            .field("interface_path", &self.interface_path)
+            .finish()
+    }
+}
+
+impl TryFrom<RegistryData> for UsbDevice {
+    type Error = ScanError;
+    fn try_from(value: RegistryData) -> Result<Self, Self::Error> {
+        let os_str = value.try_into_os_string()?;
+        let data = os_str
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "unsupported registry value"))?;
+        let ids = UsbVidPid::try_from((find_id(data, "VID_"), find_id(data, "PID_")))
+            .map_err(|e| ScanError::InvalidRegistryData(e, os_str.clone()))?;
+        // The interface path is "\\?\<bus>#<hardware id>#<instance>#<interface class guid>"
+        let mut parts = data.trim_start_matches("\\\\?\\").split('#');
+        let (bus, hardware, instance) = (parts.next(), parts.next(), parts.next());
+        let instance_id = [bus, hardware, instance]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&str>>()
+            .join("\\")
+            .into();
+        let serial = instance
+            .filter(|instance| !instance.contains('&'))
+            .map(OsString::from);
+        Ok(Self {
+            ids,
+            serial,
+            instance_id,
+            interface_path: os_str,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for UsbDevice {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("UsbDevice", 5)?;
+        state.serialize_field("vid", &self.vid())?;
+        state.serialize_field("pid", &self.pid())?;
+        state.serialize_field("serial", &self.serial.as_ref().map(|s| s.to_string_lossy()))?;
+        state.serialize_field("instance_id", &self.instance_id.to_string_lossy())?;
+        state.serialize_field("interface_path", &self.interface_path.to_string_lossy())?;
+        state.end()
+    }
+}
+
 #[cfg(feature = "serde")]
 impl serde::Serialize for UsbVidPid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -397,7 +534,10 @@ impl From<StatusHandle> for RecepientHandle {
 /// Register to receive device notifications for DBT_DEVTYP_DEVICE_INTERFACE or DBT_DEVTYP_HANDLE.
 /// We wrap this registration process. To extend support for other kinds of devices, see:
 /// https://learn.microsoft.com/en-us/windows-hardware/drivers/install/system-defined-device-setup-classes-available-to-vendors?redirectedfrom=MSDN
-pub struct NotificationRegistry(Vec<GUID>);
+pub struct NotificationRegistry {
+    guids: Vec<GUID>,
+    filter: Vec<UsbVidPid>,
+}
 impl NotificationRegistry {
     /// Windows CE USB ActiveSync Devices
     pub const WCEUSBS: GUID =
@@ -414,7 +554,10 @@ impl NotificationRegistry {
 
     /// Create a new registry with fixed capacity
     pub fn with_capacity(capacity: usize) -> Self {
-        Self(Vec::with_capacity(capacity))
+        Self {
+            guids: Vec::with_capacity(capacity),
+            filter: Vec::new(),
+        }
     }
 
     /// Helper to add all USB serial port notifications
@@ -426,17 +569,36 @@ impl NotificationRegistry {
 
     /// Add a GUID to the registration
     pub fn with(mut self, guid: GUID) -> Self {
-        self.0.push(guid);
+        self.guids.push(guid);
         self
     }
 
-    pub fn spawn<N>(self, n: N) -> Result<DeviceNotificationListener, ScanError>
+    /// Only emit port arrivals for devices matching one of these Vendor/Product ID's. The filter
+    /// is applied by the window procedure, so the [`DeviceNotificationStream`] never yields
+    /// arrivals for other ports. The filter does not apply to
+    /// [`NotificationRegistry::register_service`].
+    ///
+    /// NOTE the registry lookup of a port will fail once the device has been removed, so removal
+    /// events are never filtered. Arrivals whose registry lookup fails are dropped with a warning.
+    pub fn with_filter<'v, 'p, V, P>(mut self, ids: Vec<(V, P)>) -> Result<Self, ParseIntError>
+    where
+        V: Into<Cow<'v, str>>,
+        P: Into<Cow<'p, str>>,
+    {
+        for id in ids {
+            self.filter.push(UsbVidPid::try_from(id)?);
+        }
+        Ok(self)
+    }
+
+    pub fn spawn<N>(mut self, n: N) -> Result<DeviceNotificationListener, ScanError>
     where
         N: Into<OsString> + Send + Sync + 'static,
     {
         let name: OsString = n.into();
         let window = name.clone();
-        let ours = Arc::new(DeviceNotificationData::new()?);
+        let filter = std::mem::take(&mut self.filter);
+        let ours = Arc::new(DeviceNotificationData::new(filter)?);
         let theirs = Arc::clone(&ours);
         let join_handle = std::thread::spawn(move || unsafe {
             device_notification_window_dispatcher(name, self, Arc::into_raw(theirs) as _)
@@ -448,12 +610,23 @@ impl NotificationRegistry {
         })
     }
 
+    /// Register the GUID's against a service handle instead of spawning a window thread. The
+    /// device events are delivered to the service control handler, and are received through the
+    /// [`crate::message::ServiceMessageStream`] as [`crate::message::ServiceMessageEx::DeviceEvent`].
+    ///
+    /// NOTE the returned handles must be kept alive for as long as notifications are wanted. The
+    /// notifications are unregistered when the handles are dropped.
+    pub fn register_service(self, handle: &StatusHandle) -> io::Result<Vec<RegistrationHandle>> {
+        let recepient = RecepientHandle::from(handle.clone());
+        self.register(&recepient, recepient.discriminant())
+    }
+
     /// Collect the GUID's and register them for a window handle. NOTE that this method is private
     /// and not called directly.  The registration is expected to be passed to another thread which
     /// starts the listener
     fn register<H: AsRawHandle>(self, raw: &H, kind: u32) -> io::Result<Vec<RegistrationHandle>> {
         // Safety: We initialize the DEV_BROADCAST_DEVICEINTERFACE_W header correctly before use.
-        self.0
+        self.guids
             .into_iter()
             .map(|guid| {
                 let handle = unsafe {
@@ -479,25 +652,52 @@ impl NotificationRegistry {
 struct DeviceNotificationData {
     queue: SegQueue<Option<DeviceEvent>>,
     waker: Mutex<Option<Waker>>,
+    filter: Vec<UsbVidPid>,
 }
 
 impl DeviceNotificationData {
-    fn new() -> Result<Self, ScanError> {
+    fn new(filter: Vec<UsbVidPid>) -> Result<Self, ScanError> {
         let queue = SegQueue::new();
         let devices = self::scan()?;
-        for (port, _vidpid) in devices.into_iter() {
+        for (port, device) in devices.into_iter() {
+            if !Self::matches(&filter, &device) {
+                debug!(?port, ?device, "ignoring existing USB device");
+                continue;
+            }
             debug!(?port, "found existing USB device");
             queue.push(Some(DeviceEvent {
-                ty: DeviceEventType::Arrival,
+                ty: DeviceEventType::Existing,
                 data: DeviceEventData::Port(port),
             }));
         }
         Ok(Self {
             queue,
             waker: Mutex::new(None),
+            filter,
         })
     }
 
+    fn matches(filter: &[UsbVidPid], device: &UsbDevice) -> bool {
+        filter.is_empty() || filter.iter().any(|id| device.matches(&id.vid(), &id.pid()))
+    }
+
+    /// Test if an event passes the Vendor/Product ID filter. Only port arrivals are filtered
+    fn accepts(&self, ev: &DeviceEvent) -> bool {
+        match ev {
+            DeviceEvent {
+                ty: DeviceEventType::Arrival,
+                data: DeviceEventData::Port(port),
+            } if !self.filter.is_empty() => match scan_for(port) {
+                Ok(device) => Self::matches(&self.filter, &device),
+                Err(error) => {
+                    warn!(?port, ?error, "dropping arrival of unknown device");
+                    false
+                }
+            },
+            _ => true,
+        }
+    }
+
     fn try_wake(&self) -> &Self {
         if let Some(waker) = &self.waker.lock().as_ref() {
             waker.wake_by_ref()
@@ -545,17 +745,26 @@ impl DeviceNotificationListener {
 
     pub fn scan(&self) -> Result<&Self, ScanError> {
         let devices = self::scan()?;
-        for (port, _) in devices.into_iter() {
+        for (port, device) in devices.into_iter() {
+            if !DeviceNotificationData::matches(&self.context.filter, &device) {
+                continue;
+            }
             debug!(?port, "found USB device");
             self.context.queue.push(Some(DeviceEvent {
-                ty: DeviceEventType::Arrival,
+                ty: DeviceEventType::Existing,
                 data: DeviceEventData::Port(port),
             }));
         }
         Ok(self)
     }
 
+    /// Close the window and join the window thread, returning the result of the window
+    /// dispatcher. Closing an already closed listener is a no-op
     pub fn close(&mut self) -> io::Result<()> {
+        let Some(jh) = self.join_handle.take() else {
+            return Ok(());
+        };
+
         // Find the window so we can close it
         trace!(window = ?self.window, "closing device notification listener");
         let wide = to_wide(self.window.clone());
@@ -575,19 +784,28 @@ impl DeviceNotificationListener {
                 _ => Ok(()),
             }
         }?;
-        let jh = self.join_handle.take().ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                "Already closed DeviceNotificationListener",
-            )
-        })?;
-        jh.join()
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "join error"))?
+        match jh.join() {
+            Ok(result) => result,
+            Err(panic) => {
+                let reason = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("window dispatcher panicked => {reason}"),
+                ))
+            }
+        }
     }
 }
 
 impl Drop for DeviceNotificationListener {
     fn drop(&mut self) {
+        if self.join_handle.is_none() {
+            return;
+        }
         match self.close() {
             Ok(_) => trace!(window=?self.window, "DeviceNotificationListener drop OK"),
             Err(error) => {
@@ -621,7 +839,11 @@ impl Stream for DeviceNotificationStream {
 
 #[derive(Debug)]
 pub enum PlugEvent {
+    /// A port was connected while listening
     Plug(OsString),
+    /// A port was already connected when the listener started, or when the listener rescanned
+    Present(OsString),
+    /// A port was removed
     Unplug(OsString),
 }
 
@@ -631,6 +853,10 @@ pub fn plug_events(ev: DeviceEvent) -> Option<PlugEvent> {
             ty: DeviceEventType::Arrival,
             data: DeviceEventData::Port(port),
         } => Some(PlugEvent::Plug(port)),
+        DeviceEvent {
+            ty: DeviceEventType::Existing,
+            data: DeviceEventData::Port(port),
+        } => Some(PlugEvent::Present(port)),
         DeviceEvent {
             ty: DeviceEventType::RemoveComplete,
             data: DeviceEventData::Port(port),
@@ -674,22 +900,40 @@ pub struct TrackedPort {
     pub port: OsString,
     /// The Vendor/Product ID's of the serial port
     pub ids: UsbVidPid,
+    /// The device instance ID. See [`UsbDevice::instance_id`]
+    pub instance_id: OsString,
     /// A future which resolves when the COM port is unplugged
     pub unplugged: Unplugged,
 }
 
 impl TrackedPort {
-    pub fn track(port: OsString, ids: UsbVidPid) -> io::Result<(Sender, TrackedPort)> {
+    pub fn track(
+        port: OsString,
+        ids: UsbVidPid,
+        instance_id: OsString,
+    ) -> io::Result<(Sender, TrackedPort)> {
         let (sender, receiver) = wait::oneshot()?;
         let port = TrackedPort {
             port,
             ids,
+            instance_id,
             unplugged: Unplugged::Waiting { inner: receiver },
         };
         Ok((sender, port))
     }
 }
 
+/// How [`Tracking`] identifies a device between a plug and an unplug
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingKey {
+    /// The COM port name. IE: COM4
+    #[default]
+    Port,
+    /// The device instance ID, which is stable when the device is plugged back in on a different
+    /// COM port. See [`UsbDevice::instance_id`]
+    InstanceId,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum TrackingError {
     #[error("io error => {0}")]
@@ -708,12 +952,34 @@ pin_project! {
             #[pin]
             inner: St,
             ids: Vec<UsbVidPid>,
-            cache: HashMap<OsString, Sender>
+            key: TrackingKey,
+            // The tracked devices by key, with the port each device was last seen on
+            cache: HashMap<OsString, (OsString, Sender)>,
+            // Log and skip a failed scan instead of yielding the error
+            skip_scan_errors: bool,
         },
         Complete
     }
 }
 
+impl<St> Tracking<St> {
+    /// Log and skip a plug event when the device can not be found in the registry, instead of
+    /// yielding a [`TrackingError::Scan`]. One flaky device then can not stop a consumer which
+    /// ends on the first error from tracking every other device.
+    ///
+    /// NOTE errors are never fatal to the stream itself. Tracking continues after any error and
+    /// only ends when the inner stream ends
+    pub fn skip_scan_errors(mut self) -> Self {
+        if let Tracking::Streaming {
+            skip_scan_errors, ..
+        } = &mut self
+        {
+            *skip_scan_errors = true;
+        }
+        self
+    }
+}
+
 impl<St> Stream for Tracking<St>
 where
     St: Stream<Item = PlugEvent>,
@@ -722,32 +988,66 @@ where
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         loop {
             match self.as_mut().project() {
-                TrackingProj::Streaming { inner, ids, cache } => match inner.poll_next(cx) {
+                TrackingProj::Streaming {
+                    inner,
+                    ids,
+                    key,
+                    cache,
+                    skip_scan_errors,
+                } => match inner.poll_next(cx) {
                     Poll::Pending => break Poll::Pending,
                     Poll::Ready(None) => {
                         self.project_replace(Self::Complete);
                         break Poll::Ready(None);
                     }
-                    Poll::Ready(Some(PlugEvent::Plug(port))) => match scan_for(&port) {
-                        Err(e) => break Poll::Ready(Some(Err(e.into()))),
-                        Ok(id) => match ids.iter().find(|test| **test == id) {
-                            None => debug!(?port, ?id, "ignoring com device"),
-                            Some(id) => match TrackedPort::track(port.clone(), *id) {
-                                Err(e) => break Poll::Ready(Some(Err(e.into()))),
-                                Ok((sender, tracked)) => {
-                                    cache.insert(port.clone(), sender);
-                                    break Poll::Ready(Some(Ok(tracked)));
+                    Poll::Ready(Some(PlugEvent::Plug(port) | PlugEvent::Present(port))) => {
+                        match scan_for(&port) {
+                            Err(error) if *skip_scan_errors => {
+                                warn!(?port, ?error, "skipping device after scan error")
+                            }
+                            Err(e) => break Poll::Ready(Some(Err(e.into()))),
+                            Ok(device) => match ids.iter().find(|test| **test == device.ids()) {
+                                None => debug!(?port, ?device, "ignoring com device"),
+                                Some(id) => {
+                                    let instance_id = device.instance_id().clone();
+                                    let cache_key = match key {
+                                        TrackingKey::Port => port.clone(),
+                                        TrackingKey::InstanceId => instance_id.clone(),
+                                    };
+                                    match TrackedPort::track(port.clone(), *id, instance_id) {
+                                        Err(e) => break Poll::Ready(Some(Err(e.into()))),
+                                        Ok((sender, tracked)) => {
+                                            let entry = (port.clone(), sender);
+                                            // We missed the unplug of a device which is back, so
+                                            // the previous tracked port is signaled now
+                                            if let Some((old, stale)) =
+                                                cache.insert(cache_key, entry)
+                                            {
+                                                warn!(?old, ?port, "replacing stale tracked port");
+                                                if let Err(error) = stale.set() {
+                                                    warn!(?error, "failed to signal stale port");
+                                                }
+                                            }
+                                            break Poll::Ready(Some(Ok(tracked)));
+                                        }
+                                    }
                                 }
                             },
-                        },
-                    },
-                    Poll::Ready(Some(PlugEvent::Unplug(port))) => match cache.remove(&port) {
-                        None => warn!(?port, "untracked port"),
-                        Some(ids) => match ids.set() {
-                            Ok(_) => debug!(?port, "unplugged signal sent"),
-                            Err(e) => break Poll::Ready(Some(Err(e.into()))),
-                        },
-                    },
+                        }
+                    }
+                    Poll::Ready(Some(PlugEvent::Unplug(port))) => {
+                        let found = cache
+                            .iter()
+                            .find(|(_, (tracked, _))| *tracked == port)
+                            .map(|(k, _)| k.clone());
+                        match found.and_then(|k| cache.remove(&k)) {
+                            None => warn!(?port, "untracked port"),
+                            Some((_, sender)) => match sender.set() {
+                                Ok(_) => debug!(?port, "unplugged signal sent"),
+                                Err(e) => break Poll::Ready(Some(Err(e.into()))),
+                            },
+                        }
+                    }
                 },
                 TrackingProj::Complete => {
                     panic!("Watch must not be polled after stream has finished")
@@ -759,6 +1059,22 @@ where
 
 pub trait DeviceStreamExt: Stream<Item = PlugEvent> {
     fn track<'v, 'p, V, P>(self, ids: Vec<(V, P)>) -> Result<Tracking<Self>, ParseIntError>
+    where
+        V: Into<Cow<'v, str>>,
+        P: Into<Cow<'p, str>>,
+        Self: Sized,
+    {
+        self.track_by(ids, TrackingKey::Port)
+    }
+
+    /// Like [`DeviceStreamExt::track`] except the devices are identified by `key`. When a tracked
+    /// device is plugged in again before its unplug was seen, the previous [`TrackedPort`] is
+    /// signaled as unplugged
+    fn track_by<'v, 'p, V, P>(
+        self,
+        ids: Vec<(V, P)>,
+        key: TrackingKey,
+    ) -> Result<Tracking<Self>, ParseIntError>
     where
         V: Into<Cow<'v, str>>,
         P: Into<Cow<'p, str>>,
@@ -771,13 +1087,137 @@ pub trait DeviceStreamExt: Stream<Item = PlugEvent> {
         Ok(Tracking::Streaming {
             inner: self,
             ids: collection,
+            key,
             cache: HashMap::new(),
+            skip_scan_errors: false,
+        })
+    }
+
+    /// Suppress an unplug when the same port is plugged back in within the window. USB hubs will
+    /// often remove and re-add a device when the device re-enumerates. When the port reappears
+    /// in time neither the unplug nor the plug is emitted. A genuine unplug is emitted once the
+    /// window elapses with no matching plug, or when the inner stream ends.
+    ///
+    /// NOTE a oneshot timer is armed for the earliest pending unplug, so an unplug is emitted as
+    /// soon as its window elapses. The timer is idle while no unplug is pending.
+    fn debounce(self, window: Duration) -> io::Result<Debounce<Self>>
+    where
+        Self: Sized,
+    {
+        let pool = TimerPool::new(&TimerThreadpoolOptions::default())?;
+        Ok(Debounce {
+            inner: self,
+            pool: Some(pool),
+            timer: None,
+            window,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+            done: false,
         })
     }
 }
 
 impl<T: ?Sized> DeviceStreamExt for T where T: Stream<Item = PlugEvent> {}
 
+pin_project! {
+    /// A stream of [`PlugEvent`] with re-enumeration bounce removed. See
+    /// [`DeviceStreamExt::debounce`]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Debounce<St> {
+        #[pin]
+        inner: St,
+        // The pool is idle while no unplug is pending, otherwise it is owned by the timer
+        pool: Option<TimerPool>,
+        timer: Option<BoxFuture<'static, TimerPool>>,
+        window: Duration,
+        pending: HashMap<OsString, Instant>,
+        ready: VecDeque<PlugEvent>,
+        done: bool,
+    }
+}
+
+impl<St> Stream for Debounce<St>
+where
+    St: Stream<Item = PlugEvent>,
+{
+    type Item = PlugEvent;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(ev) = this.ready.pop_front() {
+                break Poll::Ready(Some(ev));
+            }
+            if *this.done {
+                break Poll::Ready(None);
+            }
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(PlugEvent::Unplug(port))) => {
+                    trace!(?port, "debouncing unplug");
+                    this.pending.insert(port, Instant::now() + *this.window);
+                    continue;
+                }
+                Poll::Ready(Some(PlugEvent::Plug(port) | PlugEvent::Present(port)))
+                    if this.pending.remove(&port).is_some() =>
+                {
+                    debug!(?port, "suppressed unplug bounce");
+                    continue;
+                }
+                Poll::Ready(Some(ev)) => break Poll::Ready(Some(ev)),
+                Poll::Ready(None) => {
+                    // Flush genuine unplugs before the stream ends
+                    this.ready.extend(
+                        this.pending
+                            .drain()
+                            .map(|(port, _)| PlugEvent::Unplug(port)),
+                    );
+                    *this.done = true;
+                    continue;
+                }
+                Poll::Pending if this.pending.is_empty() => break Poll::Pending,
+                Poll::Pending => {}
+            }
+            if this.timer.is_none() {
+                match (this.pending.values().min(), this.pool.take()) {
+                    (Some(due), Some(pool)) => {
+                        let duration = due.saturating_duration_since(Instant::now());
+                        *this.timer = Some(oneshot(pool, duration));
+                    }
+                    (_, pool) => {
+                        *this.pool = pool;
+                        break Poll::Pending;
+                    }
+                }
+            }
+            let Some(timer) = this.timer.as_mut() else {
+                break Poll::Pending;
+            };
+            let pool = ready!(timer.poll_unpin(cx));
+            *this.timer = None;
+            *this.pool = Some(pool);
+            let now = Instant::now();
+            let expired = this
+                .pending
+                .iter()
+                .filter(|(_, due)| **due <= now)
+                .map(|(port, _)| port.clone())
+                .collect::<Vec<_>>();
+            for port in expired {
+                this.pending.remove(&port);
+                this.ready.push_back(PlugEvent::Unplug(port));
+            }
+        }
+    }
+}
+
+/// Wait for a oneshot timer and hand the pool back so the next deadline can be armed
+fn oneshot(mut pool: TimerPool, duration: Duration) -> BoxFuture<'static, TimerPool> {
+    async move {
+        pool.oneshot(duration).await.start().await;
+        pool
+    }
+    .boxed()
+}
+
 pub mod prelude {
     pub use super::DeviceStreamExt;
 }
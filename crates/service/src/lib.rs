@@ -6,4 +6,9 @@ pub mod util;
 pub mod device;
 pub mod message;
 pub mod status;
+pub use msft_runtime as runtime;
+
+// NOTE the service macro drives an async service routine with futures when runtime = "none"
+#[doc(hidden)]
+pub use futures;
 pub use msft_service_macros::*;
@@ -1,25 +1,42 @@
 //! Wrappers around windows_sys Service Control Message.  The Service Control Message is a message
 //! from the kernel that is passed to system services. For additional details see:
 //! https://learn.microsoft.com/en-us/windows/win32/api/winsvc/nc-winsvc-lphandler_function_ex
-use crate::util::{guid::Guid, sealed::Sealed, wchar};
+use crate::{
+    guid,
+    runtime::common::abort_on_unwind,
+    util::{
+        guid::Guid,
+        hkey::{self, PredefinedHkey, RegistryValues},
+        sealed::Sealed,
+        wchar,
+    },
+};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use std::{
     error,
-    ffi::{c_void, OsString},
+    ffi::{c_void, OsStr, OsString},
     fmt,
+    future::Future,
+    io,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll, Waker},
 };
 
-use crossbeam::queue::SegQueue;
-use futures::Stream;
+use crossbeam::queue::{ArrayQueue, SegQueue};
+use futures::{
+    future::{self, Either},
+    pin_mut, Stream, StreamExt,
+};
 use parking_lot::Mutex;
+use tokio::sync::watch;
 use tracing::{debug, error, warn};
 use windows_sys::Win32::{
-    Foundation::NO_ERROR,
-    System::{Power::*, RemoteDesktop::*, Services::*, SystemServices::*},
+    Foundation::{FILETIME, HANDLE, NO_ERROR, SYSTEMTIME},
+    System::{
+        Power::*, RemoteDesktop::*, Services::*, SystemServices::*, Time::FileTimeToSystemTime,
+    },
     UI::WindowsAndMessaging::*,
 };
 
@@ -100,14 +117,41 @@ impl fmt::Display for ServiceMessageEx {
             Self::DeviceEvent(e) => write!(f, "device event type => {e}"),
             Self::HardwareProfileChange(p) => write!(f, "hardware profile change => {p}"),
             Self::PowerEvent(ev) => write!(f, "power event => {ev}"),
-            Self::SessionChange(s, _) => write!(f, "session change => {s} [[TODO]]"),
-            Self::TimeChange(_) => write!(f, "time change => [[TODO]]"),
+            Self::SessionChange(s, n) => {
+                write!(f, "session change => {s} (session {})", n.dwSessionId)
+            }
+            Self::TimeChange(t) => write!(
+                f,
+                "time change => {} -> {}",
+                DisplayFileTime(t.liOldTime),
+                DisplayFileTime(t.liNewTime)
+            ),
             Self::TriggerEvent => write!(f, "trigger event"),
             Self::UserDefined(c, e, _) => write!(f, "user defined => {c} {e}"),
         }
     }
 }
 
+/// Format a FILETIME (100ns intervals since January 1, 1601 UTC) as a UTC SYSTEMTIME
+struct DisplayFileTime(i64);
+impl fmt::Display for DisplayFileTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filetime = FILETIME {
+            dwLowDateTime: self.0 as u32,
+            dwHighDateTime: (self.0 >> 32) as u32,
+        };
+        let mut st: SYSTEMTIME = unsafe { std::mem::zeroed() };
+        match unsafe { FileTimeToSystemTime(&filetime, &mut st) } {
+            0 => write!(f, "{}", self.0),
+            _ => write!(
+                f,
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} UTC",
+                st.wYear, st.wMonth, st.wDay, st.wHour, st.wMinute, st.wSecond, st.wMilliseconds
+            ),
+        }
+    }
+}
+
 impl fmt::Debug for ServiceMessageEx {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self)
@@ -124,6 +168,9 @@ pub enum DeviceEventType {
     QueryRemoveComplete = DBT_DEVICEQUERYREMOVEFAILED,
     RemovePending = DBT_DEVICEREMOVEPENDING,
     CustomEvent = DBT_CUSTOMEVENT,
+    /// Not sent by windows. A device that was already connected when a
+    /// [`crate::device::DeviceNotificationListener`] started listening, or was rescanned
+    Existing = u32::MAX,
 }
 
 impl fmt::Display for DeviceEventType {
@@ -135,6 +182,7 @@ impl fmt::Display for DeviceEventType {
             Self::QueryRemoveComplete => write!(f, "device query remove complete"),
             Self::RemovePending => write!(f, "device remove pending"),
             Self::CustomEvent => write!(f, "device custom event"),
+            Self::Existing => write!(f, "device existing"),
         }
     }
 }
@@ -145,27 +193,27 @@ impl fmt::Display for DeviceEventType {
 /// type and so we store them as a reference
 #[repr(C)]
 pub enum DeviceEventData {
-    /// Contains information about a class of devices
-    Interface(),
-    /// Contains information about a file system handle
-    Handle(),
+    /// Contains information about a class of devices. The class guid and the device name
+    Interface(Guid, OsString),
+    /// Contains information about a file system handle. The handle and the event guid. NOTE the
+    /// event guid is only meaningful for a [`DeviceEventType::CustomEvent`]
+    Handle(HANDLE, Guid),
     /// Contains information about a OEM-defined device type
     Oem(DEV_BROADCAST_OEM),
     /// Contains information about a modem, serial, or parallel port
     Port(OsString),
     /// Contains information about a logical volume
-    Volume(DEV_BROADCAST_VOLUME),
+    Volume(VolumeEvent),
 }
 
 impl fmt::Display for DeviceEventData {
-    // TODO we should write things like the "port" name etc.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Interface() => write!(f, "interface => [[TODO]]"),
-            Self::Handle() => write!(f, "handle => [[TODO]]"),
-            Self::Oem(_) => write!(f, "oem => [[TODO]]"),
-            Self::Port(_) => write!(f, "port => [[TODO]]"),
-            Self::Volume(_) => write!(f, "volume => [[TODO]]"),
+            Self::Interface(class, name) => write!(f, "interface => {class} {name:?}"),
+            Self::Handle(handle, event) => write!(f, "handle => {handle} {event}"),
+            Self::Oem(oem) => write!(f, "oem => {}", oem.dbco_identifier),
+            Self::Port(port) => write!(f, "port => {port:?}"),
+            Self::Volume(volume) => write!(f, "volume => {:?}", volume.drives),
         }
     }
 }
@@ -173,11 +221,15 @@ impl fmt::Display for DeviceEventData {
 impl fmt::Debug for DeviceEventData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Interface() => write!(f, "DeviceEventData::Interface(TODO)"),
-            Self::Handle() => write!(f, "DeviceEventData::Interface(TODO)"),
-            Self::Oem(_) => write!(f, "DeviceEventData::Interface(TODO)"),
+            Self::Interface(class, name) => {
+                write!(f, "DeviceEventData::Interface({class:?}, {name:?})")
+            }
+            Self::Handle(handle, event) => {
+                write!(f, "DeviceEventData::Handle({handle}, {event:?})")
+            }
+            Self::Oem(oem) => write!(f, "DeviceEventData::Oem({})", oem.dbco_identifier),
             Self::Port(port) => write!(f, "DeviceEventData::Port({:?})", port),
-            Self::Volume(_) => write!(f, "DeviceEventData::Interface(TODO)"),
+            Self::Volume(volume) => write!(f, "DeviceEventData::Volume({volume:?})"),
         }
     }
 }
@@ -187,10 +239,25 @@ impl TryCast for DeviceEventData {
     unsafe fn try_cast(data: *mut c_void) -> Option<Self> {
         let broadcast = &mut *(data as *mut DEV_BROADCAST_HDR);
         match broadcast.dbch_devicetype {
-            DBT_DEVTYP_HANDLE => None,
-            DBT_DEVTYP_OEM => None,
-            DBT_DEVTYP_VOLUME => None,
-            DBT_DEVTYP_DEVICEINTERFACE => None,
+            DBT_DEVTYP_HANDLE => {
+                let handle = &*(data as *const DEV_BROADCAST_HANDLE);
+                Some(Self::Handle(
+                    handle.dbch_handle,
+                    Guid::from(handle.dbch_eventguid),
+                ))
+            }
+            DBT_DEVTYP_OEM => Some(Self::Oem(*(data as *const DEV_BROADCAST_OEM))),
+            DBT_DEVTYP_VOLUME => {
+                let volume = *(data as *const DEV_BROADCAST_VOLUME);
+                Some(Self::Volume(VolumeEvent::from(volume)))
+            }
+            DBT_DEVTYP_DEVICEINTERFACE => {
+                let interface = &*(data as *const DEV_BROADCAST_DEVICEINTERFACE_W);
+                Some(Self::Interface(
+                    Guid::from(interface.dbcc_classguid),
+                    wchar::from_wide(interface.dbcc_name.as_ptr()),
+                ))
+            }
             DBT_DEVTYP_PORT => {
                 let port = &*(data as *const DEV_BROADCAST_PORT_W);
                 Some(Self::Port(wchar::from_wide(port.dbcp_name.as_ptr())))
@@ -202,6 +269,35 @@ impl TryCast for DeviceEventData {
 
 impl Sealed for DeviceEventData {}
 
+/// The logical volumes affected by a device event
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/dbt/ns-dbt-dev_broadcast_volume)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeEvent {
+    /// The drive letters of the affected volumes, IE: `['C', 'D']`
+    pub drives: Vec<char>,
+    /// The change affects media in the drive. Otherwise the change affects the physical device
+    pub is_media: bool,
+    /// The volume is a network volume
+    pub is_net: bool,
+}
+
+impl From<DEV_BROADCAST_VOLUME> for VolumeEvent {
+    fn from(volume: DEV_BROADCAST_VOLUME) -> Self {
+        // Bit 0 is drive A, bit 1 is drive B, and so on
+        let drives = ('A'..='Z')
+            .enumerate()
+            .filter(|(bit, _)| volume.dbcv_unitmask & (1 << bit) != 0)
+            .map(|(_, drive)| drive)
+            .collect();
+        Self {
+            drives,
+            is_media: volume.dbcv_flags & DBTF_MEDIA != 0,
+            is_net: volume.dbcv_flags & DBTF_NET != 0,
+        }
+    }
+}
+
 pub struct DeviceEvent {
     pub ty: DeviceEventType,
     pub data: DeviceEventData,
@@ -209,16 +305,20 @@ pub struct DeviceEvent {
 impl DeviceEvent {
     /// Safety: Data must be a Option<DEV_BROADCAST_HDR>
     pub(crate) unsafe fn try_parse(event_type: u32, data: *mut c_void) -> Option<Self> {
+        let ty = match DeviceEventType::from_u32(event_type)? {
+            DeviceEventType::Existing => return None,
+            ty => ty,
+        };
         Some(DeviceEvent {
-            ty: DeviceEventType::from_u32(event_type)?,
+            ty,
             data: DeviceEventData::try_cast(data)?,
         })
     }
 
-    /// Consume the device event and return the
+    /// Consume the device event and return the port of an arrival or an existing device
     pub fn filter_port_arrival(self) -> Result<OsString, DeviceEvent> {
         match self.ty {
-            DeviceEventType::Arrival => match self.data {
+            DeviceEventType::Arrival | DeviceEventType::Existing => match self.data {
                 DeviceEventData::Port(port) => Ok(port),
                 _ => Err(self),
             },
@@ -324,8 +424,8 @@ pub enum PowerBroadcastSetting {
     MonitorPowerOn(bool),
     /// The battery saver has been turned off (false) or turned on (true)
     PowerSavingStatus(bool),
-    //Energy Saver Status See [`EnergySaverStatus`]
-    //EnergySaverStatus(EnergySaverStatus),
+    /// The energy saver status has changed. See [`EnergySaverStatus`]
+    EnergySaverStatus(EnergySaverStatus),
     /// See [`PowerschemePersonality`]
     PowerschemePersonality(PowerschemePersonality),
     /// The display associated with the applications session has changed state
@@ -348,6 +448,7 @@ impl fmt::Display for PowerBroadcastSetting {
             Self::LidswitchStateChange(lid) => write!(f, "lid switch => {lid}"),
             Self::MonitorPowerOn(status) => write!(f, "monitor power status => {status}"),
             Self::PowerSavingStatus(status) => write!(f, "power savings status => {status}"),
+            Self::EnergySaverStatus(status) => write!(f, "energy saver status => {status}"),
             Self::PowerschemePersonality(p) => write!(f, "power personality => {p}"),
             Self::SessionDisplayStatus(sess) => write!(f, "session display => {sess}"),
             Self::SessionUserPresence(u) => write!(f, "session user presence => {u}"),
@@ -394,7 +495,10 @@ impl PowerBroadcastSetting {
                 let battery_saver = if data == 0 { false } else { true };
                 Some(Self::PowerSavingStatus(battery_saver))
             }
-            //guid if Guid::from(guid) == Guid(GUID_ENERY_SAVER_STATUS) => unimplemented!(),
+            guid if Guid::from(guid) == Guid(GUID_ENERGY_SAVER_STATUS) => {
+                let status = EnergySaverStatus::from_u32(*(broadcast.Data.as_ptr() as *const u32));
+                status.map(Self::EnergySaverStatus)
+            }
             guid if Guid::from(guid) == Guid(GUID_POWERSCHEME_PERSONALITY) => {
                 let guid = *(broadcast.Data.as_ptr() as *const windows_sys::core::GUID);
                 PowerschemePersonality::try_from_guid(guid).map(Self::PowerschemePersonality)
@@ -531,15 +635,33 @@ impl fmt::Display for LidswitchState {
     }
 }
 
-/*
+/// NOTE windows_sys does not export this guid. Windows 10 and later
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/power/power-setting-guids)
+const GUID_ENERGY_SAVER_STATUS: windows_sys::core::GUID =
+    guid!(0x550e8400, 0xe29b, 0x41d4, 0xa7, 0x16, 0x44, 0x66, 0x55, 0x44, 0x00, 0x00);
+
 /// The energy saver status has changed
-#[derive(FromPrimitive)]
+#[derive(FromPrimitive, Debug)]
+#[repr(u32)]
 pub enum EnergySaverStatus {
-    Off,
-    Standard,
-    High,
+    /// Energy saver is off
+    Off = 0,
+    /// Energy saver is on. Save energy where the user experience is not affected
+    Standard = 1,
+    /// Energy saver is on. Save energy even where the user experience is affected
+    High = 2,
+}
+
+impl fmt::Display for EnergySaverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "energy saver off"),
+            Self::Standard => write!(f, "energy saver standard"),
+            Self::High => write!(f, "energy saver high savings"),
+        }
+    }
 }
-*/
 
 /// The status code describing the reason the session state change notification was sent.
 /// See: (https://learn.microsoft.com/en-us/windows/win32/termserv/wm-wtssession-change)
@@ -618,6 +740,20 @@ impl fmt::Display for UnsupportedServiceMessage {
 impl error::Error for UnsupportedServiceMessage {}
 
 impl ServiceMessageEx /*<D>*/ {
+    /// True for the messages which end the [`ServiceMessageStream`]. IE: Stop, Preshutdown and
+    /// Shutdown
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Stop | Self::Preshutdown | Self::Shutdown)
+    }
+
+    /// The session that changed when this is a [`ServiceMessageEx::SessionChange`] message
+    pub fn session_id(&self) -> Option<u32> {
+        match self {
+            Self::SessionChange(_, notification) => Some(notification.dwSessionId),
+            _ => None,
+        }
+    }
+
     fn try_parse(
         control: u32,
         event_type: u32,
@@ -681,8 +817,104 @@ impl ServiceMessageEx /*<D>*/ {
 }
 
 /// A service spawned [`service_macros::start_service_ctrl_dispatcher`] will receive these
-/// arguments to the ServiceMain routine
-pub type Arguments = Vec<OsString>;
+/// arguments to the ServiceMain routine. By convention the first argument is the name of the
+/// service, and the remaining arguments are the start parameters passed to StartService.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/nc-winsvc-lpservice_main_functionw)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Arguments(Vec<OsString>);
+
+impl Arguments {
+    /// The name of the service, which is the first argument passed to the ServiceMain routine
+    pub fn service_name(&self) -> Option<&OsStr> {
+        self.0.first().map(OsString::as_os_str)
+    }
+
+    /// Returns true if the first argument is the name of the service
+    pub fn first_is_service_name<S: AsRef<OsStr>>(&self, name: S) -> bool {
+        self.service_name() == Some(name.as_ref())
+    }
+
+    /// Get a start parameter by index. Index 0 is the first start parameter after the service
+    /// name
+    pub fn get(&self, index: usize) -> Option<&OsStr> {
+        self.params().get(index).map(OsString::as_os_str)
+    }
+
+    /// The start parameters, not including the service name
+    pub fn params(&self) -> &[OsString] {
+        self.0.get(1..).unwrap_or_default()
+    }
+
+    /// All of the arguments, including the service name
+    pub fn as_slice(&self) -> &[OsString] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Vec<OsString> {
+        self.0
+    }
+}
+
+impl FromIterator<OsString> for Arguments {
+    fn from_iter<I: IntoIterator<Item = OsString>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl From<Vec<OsString>> for Arguments {
+    fn from(value: Vec<OsString>) -> Self {
+        Self(value)
+    }
+}
+
+/// Reload service parameters from the registry when the SCM sends a
+/// [`ServiceMessageEx::ParamChange`] message (ie: `sc control <svc> paramchange`). The latest
+/// values are published to a watch channel so that the service body can react to new parameters.
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/nc-winsvc-lphandler_function_ex)
+pub struct ParamWatch {
+    parent: PredefinedHkey,
+    subkey: OsString,
+    sender: watch::Sender<RegistryValues>,
+}
+
+impl ParamWatch {
+    /// Read the registry key and return a receiver containing the current values
+    pub fn new<K>(
+        parent: PredefinedHkey,
+        subkey: K,
+    ) -> io::Result<(Self, watch::Receiver<RegistryValues>)>
+    where
+        K: Into<OsString>,
+    {
+        let subkey = subkey.into();
+        let values = hkey::open(parent, subkey.clone())?.read_values()?;
+        let (sender, receiver) = watch::channel(values);
+        let watch = Self {
+            parent,
+            subkey,
+            sender,
+        };
+        Ok((watch, receiver))
+    }
+
+    /// Read the registry key and publish the new values to all receivers
+    pub fn reload(&self) -> io::Result<()> {
+        let values = hkey::open(self.parent, self.subkey.clone())?.read_values()?;
+        self.sender.send_replace(values);
+        Ok(())
+    }
+
+    /// Reload the registry key if the message is a [`ServiceMessageEx::ParamChange`] message.
+    /// Returns true if the parameters were reloaded
+    pub fn handle(&self, message: &ServiceMessageEx) -> io::Result<bool> {
+        match message {
+            ServiceMessageEx::ParamChange => self.reload().map(|_| true),
+            _ => Ok(false),
+        }
+    }
+}
 
 /// Safety: This control handler is called from the context of the Main thread.  The main thread is
 /// guarenteed to be alive at least as long as the service routines.
@@ -695,39 +927,107 @@ pub unsafe extern "system" fn service_control_message_handler(
     event_data: *mut c_void,
     context: *mut c_void,
 ) -> u32 {
-    // NOTE that we must only construct a "borrowed" version of the message. While parsing we leak
-    // the box. When the consumer end of the event queue consumes the message, the api enforces
-    // that they will consume an "Owned" version of the message, for which the message will be
-    // dropped
-    let m = ServiceMessageEx::try_parse(control, event_type, event_data);
-    match m {
-        Ok(m) => {
-            let context = &mut *(context as *mut ServiceMessageState);
-            context.messages.push(m);
-            if let Some(waker) = context.waker.lock().as_ref() {
-                waker.wake_by_ref();
-                NO_ERROR
-            } else {
-                warn!("no waker available yet");
+    abort_on_unwind("service_control_message_handler", || {
+        // NOTE that we must only construct a "borrowed" version of the message. While parsing we
+        // leak the box. When the consumer end of the event queue consumes the message, the api
+        // enforces that they will consume an "Owned" version of the message, for which the message
+        // will be dropped
+        let m = ServiceMessageEx::try_parse(control, event_type, event_data);
+        match m {
+            Ok(m) => {
+                let context = &*(context as *const ServiceMessageState);
+                context.push(m);
+                if let Some(waker) = context.waker.lock().as_ref() {
+                    waker.wake_by_ref();
+                    NO_ERROR
+                } else {
+                    warn!("no waker available yet");
+                    NO_ERROR
+                }
+            }
+            Err(error) => {
+                error!(?error, "failed to parse service message");
                 NO_ERROR
             }
         }
-        Err(error) => {
-            error!(?error, "failed to parse service message");
-            NO_ERROR
+    })
+}
+
+/// The queue of service messages waiting to be received from the service stream
+pub enum ServiceMessageQueue {
+    /// The queue will grow as long as the service stream is not polled
+    Unbounded(SegQueue<ServiceMessageEx>),
+    /// The queue holds a fixed number of messages. When the queue is full, the oldest message is
+    /// dropped to make room for the newest message. Terminal messages are never queued, see
+    /// [`ServiceMessageState`]
+    Bounded(ArrayQueue<ServiceMessageEx>),
+}
+
+impl ServiceMessageQueue {
+    /// Push a message into the queue. Returns the message that was dropped if the queue was full
+    fn push(&self, message: ServiceMessageEx) -> Option<ServiceMessageEx> {
+        match self {
+            Self::Unbounded(queue) => {
+                queue.push(message);
+                None
+            }
+            Self::Bounded(queue) => queue.force_push(message),
+        }
+    }
+
+    fn pop(&self) -> Option<ServiceMessageEx> {
+        match self {
+            Self::Unbounded(queue) => queue.pop(),
+            Self::Bounded(queue) => queue.pop(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Unbounded(queue) => queue.len(),
+            Self::Bounded(queue) => queue.len(),
         }
     }
 }
 
+impl Default for ServiceMessageQueue {
+    fn default() -> Self {
+        Self::Unbounded(SegQueue::new())
+    }
+}
+
 #[derive(Default)]
 pub struct ServiceMessageState {
-    /// A queue of messages waiting to be received from the service stream. NOTE should we use an
-    /// array queue instead and drop old messages IE: back pressure? (probably)
-    messages: SegQueue<ServiceMessageEx>,
+    /// A queue of messages waiting to be received from the service stream.
+    messages: ServiceMessageQueue,
+    /// The first terminal message. It is latched outside of the queue so that a full queue can
+    /// never drop it, and it is received once the queue is drained
+    terminal: Mutex<Option<ServiceMessageEx>>,
     /// The "Waker" for when we have a new message ready
     waker: Mutex<Option<Waker>>,
 }
 
+impl ServiceMessageState {
+    fn push(&self, message: ServiceMessageEx) {
+        if message.is_terminal() {
+            let mut terminal = self.terminal.lock();
+            match terminal.as_ref() {
+                Some(latched) => debug!(%message, %latched, "already terminating"),
+                None => *terminal = Some(message),
+            }
+        } else if let Some(dropped) = self.messages.push(message) {
+            warn!(
+                ?dropped,
+                "service message queue full, dropped oldest message"
+            );
+        }
+    }
+
+    fn pop(&self) -> Option<ServiceMessageEx> {
+        self.messages.pop().or_else(|| self.terminal.lock().take())
+    }
+}
+
 /// A stream of service messages. The message emit from the applications "Main" thread, which is
 /// distinguished from the "ServiceMain" thread.  The kernel guarentees tht the "Main" thread will
 /// live at least as long as all "ServiceMain" threads. Therefore, we treat these threads as a
@@ -737,28 +1037,132 @@ pub struct ServiceMessageState {
 #[derive(Default)]
 pub struct ServiceMessageStream {
     state: Arc<ServiceMessageState>,
+    /// End the stream without yielding the terminal message
+    collapse_termination: bool,
+    /// A terminal message was received, the stream is finished
+    terminated: bool,
 }
 
 impl ServiceMessageStream {
+    /// Create a stream of service messages which will buffer at most `capacity` messages. When the
+    /// buffer is full the oldest message is dropped. Terminal messages are never dropped.
+    ///
+    /// Panics if the capacity is zero
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::builder().capacity(capacity).build()
+    }
+
+    /// Configure a stream of service messages
+    pub fn builder() -> ServiceMessageStreamBuilder {
+        ServiceMessageStreamBuilder::default()
+    }
+
     pub fn state(&self) -> *const ServiceMessageState {
         Arc::as_ptr(&self.state)
     }
+
+    /// Drive the stream while polling the application future, resolving when either the
+    /// application completes or the SCM asks the service to terminate. Messages which are not
+    /// terminal are dropped, so handle them in the application if needed.
+    pub async fn run_until<F>(mut self, app: F) -> RunUntil<F::Output>
+    where
+        F: Future,
+    {
+        let terminal = async {
+            while let Some(message) = self.next().await {
+                if message.is_terminal() {
+                    return Some(message);
+                }
+                debug!(%message, "ignoring service message");
+            }
+            None
+        };
+        pin_mut!(app, terminal);
+        match future::select(app, terminal).await {
+            Either::Left((output, _)) => RunUntil::Complete(output),
+            Either::Right((message, _)) => RunUntil::Terminated(message),
+        }
+    }
+}
+
+/// The outcome of [`ServiceMessageStream::run_until`]
+#[derive(Debug)]
+pub enum RunUntil<O> {
+    /// The application future completed first
+    Complete(O),
+    /// The SCM asked the service to terminate. The terminal message is None when the stream
+    /// collapses termination (see [`ServiceMessageStreamBuilder::collapse_termination`])
+    Terminated(Option<ServiceMessageEx>),
+}
+
+/// Configure a [`ServiceMessageStream`]. See [`ServiceMessageStream::builder`]
+#[derive(Debug, Default)]
+pub struct ServiceMessageStreamBuilder {
+    capacity: Option<usize>,
+    collapse_termination: bool,
+}
+
+impl ServiceMessageStreamBuilder {
+    /// Buffer at most `capacity` messages. When the buffer is full the oldest message is dropped
+    /// and the control handler still returns NO_ERROR to the SCM immediately. By default the
+    /// buffer is unbounded. Terminal messages are held outside of the buffer, so the stream
+    /// always ends after a Stop, Preshutdown or Shutdown.
+    ///
+    /// Panics (when built) if the capacity is zero
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// By default a Stop, Preshutdown or Shutdown message is yielded from the stream, and the
+    /// stream ends on the following poll. This lets the service tell why it is terminating (ie:
+    /// to report a longer wait hint on Preshutdown). Set to true to end the stream immediately
+    /// without yielding the terminal message.
+    pub fn collapse_termination(mut self, collapse: bool) -> Self {
+        self.collapse_termination = collapse;
+        self
+    }
+
+    pub fn build(self) -> ServiceMessageStream {
+        let messages = match self.capacity {
+            Some(capacity) => ServiceMessageQueue::Bounded(ArrayQueue::new(capacity)),
+            None => ServiceMessageQueue::Unbounded(SegQueue::new()),
+        };
+        let state = ServiceMessageState {
+            messages,
+            terminal: Mutex::new(None),
+            waker: Mutex::new(None),
+        };
+        ServiceMessageStream {
+            state: Arc::new(state),
+            collapse_termination: self.collapse_termination,
+            terminated: false,
+        }
+    }
 }
 
 impl Stream for ServiceMessageStream {
     type Item = ServiceMessageEx;
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut waker = self.state.waker.lock();
+        let this = self.get_mut();
+        if this.terminated {
+            return Poll::Ready(None);
+        }
+        let mut waker = this.state.waker.lock();
 
         // Diagnostic stuff
-        let pending = self.state.messages.len();
+        let pending = this.state.messages.len();
         debug!(pending, "pending SCM messages");
 
         // Maybe the caller a message
-        match self.state.messages.pop() {
-            Some(ServiceMessageEx::Stop)
-            | Some(ServiceMessageEx::Preshutdown)
-            | Some(ServiceMessageEx::Shutdown) => Poll::Ready(None),
+        match this.state.pop() {
+            Some(message) if message.is_terminal() => {
+                this.terminated = true;
+                match this.collapse_termination {
+                    true => Poll::Ready(None),
+                    false => Poll::Ready(Some(message)),
+                }
+            }
             Some(message) => Poll::Ready(Some(message)),
             None => {
                 // Some waker accounting
@@ -2,10 +2,13 @@
 
 use crate::message::{service_control_message_handler, ServiceMessageStream};
 use bitflags::bitflags;
-use std::io;
 use std::os::windows::prelude::{AsRawHandle, RawHandle};
+use std::{fmt, io};
 use tracing::error;
-use windows_sys::Win32::System::{Services::*, SystemServices::*};
+use windows_sys::Win32::{
+    Foundation::ERROR_SERVICE_SPECIFIC_ERROR,
+    System::{Services::*, SystemServices::*},
+};
 
 bitflags! {
     /// The type of service. Must set when calling SetServiceStatus.
@@ -93,7 +96,85 @@ bitflags! {
     }
 }
 
+impl ServiceControlAccept {
+    /// The registrations a service must make, in addition to accepting these controls, to receive
+    /// every message the controls imply. A service which accepts controls without making these
+    /// registrations will silently never receive the related messages.
+    ///
+    /// NOTE device events are not implied by any accepted control. See
+    /// [`ControlRegistration::DeviceNotification`]
+    pub fn required_registrations(self) -> Vec<ControlRegistration> {
+        let mut required = Vec::new();
+        if self.contains(ServiceControlAccept::POWEREVENT) {
+            required.push(ControlRegistration::PowerSettingNotification);
+        }
+        required
+    }
+}
+
+/// Some service control messages are only sent to a service after an additional registration.
+/// See [`ServiceControlAccept::required_registrations`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlRegistration {
+    /// SERVICE_CONTROL_DEVICEEVENT is never enabled by [`ServiceControlAccept`], so it is never
+    /// returned by [`ServiceControlAccept::required_registrations`]. A service which handles
+    /// device events must always call RegisterDeviceNotification with the status handle, see
+    /// [`crate::device::NotificationRegistry`]
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-registerdevicenotificationw)
+    DeviceNotification,
+    /// [`ServiceControlAccept::POWEREVENT`] only delivers the basic power broadcasts. Power
+    /// setting changes (IE: battery remaining, display state) are only sent for settings
+    /// registered with RegisterPowerSettingNotification
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-registerpowersettingnotification)
+    PowerSettingNotification,
+}
+
+/// The service specific exit code reported with ERROR_SERVICE_SPECIFIC_ERROR by the
+/// [`service_macros::service`] macro. Tells which step of the service failed when there is no
+/// win32 error code
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceFailure {
+    /// The service routine returned an error
+    Routine = 1,
+    /// The service context was not initialized before the service dispatcher started
+    Context = 2,
+    /// The private threadpool for the service could not be created
+    Threadpool = 3,
+    /// The tokio runtime for the service could not be built
+    Runtime = 4,
+}
+
+/// The value returned from an async service routine. When the routine fails, the
+/// [`service_macros::service`] macro logs the error and reports the service as stopped so that
+/// the SCM can apply the configured recovery actions.
+pub trait ServiceExit {
+    /// Returns the win32 exit code to report to the SCM when the service routine failed
+    fn exit_code(self) -> Option<u32>;
+}
+
+impl ServiceExit for () {
+    fn exit_code(self) -> Option<u32> {
+        None
+    }
+}
+
+impl<E: fmt::Debug> ServiceExit for Result<(), E> {
+    fn exit_code(self) -> Option<u32> {
+        match self {
+            Ok(()) => None,
+            Err(error) => {
+                error!(?error, "Service routine failed");
+                Some(ERROR_SERVICE_SPECIFIC_ERROR)
+            }
+        }
+    }
+}
+
 /// TODO
+#[derive(Clone)]
 pub struct StatusHandle {
     handle: isize,
     status: SERVICE_STATUS,
@@ -162,6 +243,64 @@ impl StatusHandle {
         self
     }
 
+    /// Report the service is starting. Each call increments the check point so the SCM knows the
+    /// service is making progress. The wait hint is the time in milliseconds until the next call.
+    ///
+    /// [See
+    /// also:](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/ns-winsvc-service_status)
+    pub fn report_start_pending(&mut self, wait_hint: u32) -> io::Result<()> {
+        self.report_pending(CurrentState::ServiceStartPending, wait_hint)
+    }
+
+    /// Report the service is stopping. Each call increments the check point so the SCM knows the
+    /// service is making progress. The wait hint is the time in milliseconds until the next call.
+    ///
+    /// [See
+    /// also:](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/ns-winsvc-service_status)
+    pub fn report_stop_pending(&mut self, wait_hint: u32) -> io::Result<()> {
+        self.report_pending(CurrentState::ServiceStopPending, wait_hint)
+    }
+
+    /// Report the service is running. Resets the check point and the wait hint
+    pub fn report_running(&mut self) -> io::Result<()> {
+        self.set_current_state(CurrentState::ServiceRunning)
+            .set_check_point(0)
+            .set_wait_hint(0)
+            .set_status()
+    }
+
+    /// Report the service is stopped with a win32 exit code. Resets the check point and the wait
+    /// hint
+    pub fn report_stopped(&mut self, exit_code: u32) -> io::Result<()> {
+        self.set_current_state(CurrentState::ServiceStopped)
+            .set_exit_code(exit_code)
+            .set_check_point(0)
+            .set_wait_hint(0)
+            .set_status()
+    }
+
+    fn report_pending(&mut self, state: CurrentState, wait_hint: u32) -> io::Result<()> {
+        let check_point = self.status.dwCheckPoint.wrapping_add(1);
+        self.set_current_state(state)
+            .set_check_point(check_point)
+            .set_wait_hint(wait_hint)
+            .set_status()
+    }
+
+    /// Report the service as stopped because of a service specific error. The win32 exit code is
+    /// set to ERROR_SERVICE_SPECIFIC_ERROR so that the SCM reads the service specific exit code.
+    ///
+    /// [See
+    /// also:](https://learn.microsoft.com/en-us/windows/win32/api/winsvc/ns-winsvc-service_status)
+    pub fn report_failed(&mut self, service_specific_code: u32) -> io::Result<()> {
+        self.set_current_state(CurrentState::ServiceStopped)
+            .set_exit_code(ERROR_SERVICE_SPECIFIC_ERROR)
+            .set_service_exit_code(service_specific_code)
+            .set_check_point(0)
+            .set_wait_hint(0)
+            .set_status()
+    }
+
     /// Set the status structure containing ServiceType, ServiceState, ControlsAccepted, 2 exit
     /// codes, a "progress bar" type and a "wait hint" for timeout accounting
     ///
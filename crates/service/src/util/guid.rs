@@ -1,8 +1,14 @@
 //! guid
 
 use std::ffi::OsString;
-use std::{error, fmt};
-use windows_sys::Win32::System::Rpc::{UuidFromStringW, RPC_S_INVALID_STRING_UUID};
+use std::{error, fmt, hash, io};
+use windows_sys::Win32::{
+    Foundation::S_OK,
+    System::{
+        Com::CoCreateGuid,
+        Rpc::{UuidFromStringW, RPC_S_INVALID_STRING_UUID},
+    },
+};
 
 /// Simple error type when we fail to convert a string into a guid
 #[derive(Debug)]
@@ -31,12 +37,51 @@ impl Guid {
         }
     }
 
+    /// Generate a new random Guid
+    ///
+    /// [See also](https://learn.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-cocreateguid)
+    pub fn random() -> io::Result<Self> {
+        let mut guid = unsafe { std::mem::zeroed() };
+        match unsafe { CoCreateGuid(&mut guid) } {
+            S_OK => Ok(Self(guid)),
+            hresult => Err(io::Error::from_raw_os_error(hresult)),
+        }
+    }
+
     /// Unwrap into the inner [`windows_sys::core::GUID`]
     pub fn into_inner(self) -> windows_sys::core::GUID {
         self.0
     }
 }
 
+/// Formats the canonical 8-4-4-4-12 form, which can be parsed with [`Guid::new`]
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.0.data4;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            self.0.data1,
+            self.0.data2,
+            self.0.data3,
+            d[0],
+            d[1],
+            d[2],
+            d[3],
+            d[4],
+            d[5],
+            d[6],
+            d[7]
+        )
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Guid({self})")
+    }
+}
+
 impl PartialEq for Guid {
     fn eq(&self, other: &Self) -> bool {
         self.0.data1 == other.0.data1
@@ -46,6 +91,39 @@ impl PartialEq for Guid {
     }
 }
 
+impl Eq for Guid {}
+
+/// Hashes the same fields compared by [`PartialEq`]
+impl hash::Hash for Guid {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.0.data1.hash(state);
+        self.0.data2.hash(state);
+        self.0.data3.hash(state);
+        self.0.data4.hash(state);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Guid {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Guid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Guid::new(s).map_err(serde::de::Error::custom)
+    }
+}
+
 impl From<windows_sys::core::GUID> for Guid {
     fn from(value: windows_sys::core::GUID) -> Self {
         Self(value)
@@ -1,8 +1,11 @@
 //! hkey
-use super::wchar::from_wide;
+use super::wchar::{from_nwide, from_wide, to_wide};
 use core::fmt;
-use std::{error, ffi::OsString, io};
-use windows_sys::Win32::{Foundation::ERROR_SUCCESS, System::Registry::*};
+use std::{collections::HashMap, error, ffi::OsString, io, os::windows::ffi::OsStringExt};
+use windows_sys::Win32::{
+    Foundation::ERROR_SUCCESS,
+    System::{Environment::ExpandEnvironmentStringsW, Registry::*},
+};
 
 #[derive(Debug)]
 pub struct UnexpectedRegistryData {
@@ -60,25 +63,55 @@ impl RegistryData {
         Self { data, ty }
     }
 
-    pub fn try_into_expanded_os_string(self) -> Result<OsString, UnexpectedRegistryData> {
+    /// Encode a string as REG_SZ registry data
+    pub fn sz<S: Into<OsString>>(s: S) -> Self {
+        Self::from_data(REG_SZ, Self::encode_wide(s))
+    }
+
+    /// Encode a string as REG_EXPAND_SZ registry data
+    pub fn expand_sz<S: Into<OsString>>(s: S) -> Self {
+        Self::from_data(REG_EXPAND_SZ, Self::encode_wide(s))
+    }
+
+    /// Encode a u32 as REG_DWORD registry data
+    pub fn dword(n: u32) -> Self {
+        Self::from_data(REG_DWORD, n.to_le_bytes().to_vec())
+    }
+
+    /// Encode a u64 as REG_QWORD registry data
+    pub fn qword(n: u64) -> Self {
+        Self::from_data(REG_QWORD, n.to_le_bytes().to_vec())
+    }
+
+    /// Encode a null terminated wide string as little endian bytes
+    fn encode_wide<S: Into<OsString>>(s: S) -> Vec<u8> {
+        to_wide(s).into_iter().flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Decode the data as a wide string. Registry strings are not guarenteed to be null terminated
+    fn decode_os_string(&self) -> OsString {
+        // Safety: The pointer is valid for the length of the data
+        unsafe { from_nwide(self.data.as_ptr() as _, self.data.len()) }
+    }
+
+    /// Convert the registry data into a string, expanding any environment variables of a
+    /// REG_EXPAND_SZ value (ie: %SystemRoot%)
+    pub fn try_into_expanded_os_string(self) -> io::Result<OsString> {
         match self.ty {
-            // Safety: NOTE this is unsound, as the data might not be null terminated.
-            //         TODO - make a from_nwide which excepts a len param and use this instead
-            REG_SZ => unsafe { Ok(from_wide(self.data.as_ptr() as _)) },
-            REG_EXPAND_SZ => todo!("expand the inner string"),
+            REG_SZ => Ok(self.decode_os_string()),
+            REG_EXPAND_SZ => expand_environment_strings(self.decode_os_string()),
             val => Err(UnexpectedRegistryData {
                 expect: REG_EXPAND_SZ,
                 actual: val,
                 data: self.data,
-            }),
+            }
+            .into()),
         }
     }
 
     pub fn try_into_os_string(self) -> Result<OsString, UnexpectedRegistryData> {
         match self.ty {
-            // Safety: NOTE this is unsound, as the data might not be null terminated.
-            //         TODO - make a from_nwide which excepts a len param and use this instead
-            REG_EXPAND_SZ | REG_SZ => unsafe { Ok(from_wide(self.data.as_ptr() as _)) },
+            REG_EXPAND_SZ | REG_SZ => Ok(self.decode_os_string()),
             val => Err(UnexpectedRegistryData {
                 expect: REG_SZ,
                 actual: val,
@@ -87,6 +120,30 @@ impl RegistryData {
         }
     }
 
+    /// Convert REG_MULTI_SZ registry data into its list of strings. The list ends at the first
+    /// empty string or at the end of the data, so a missing double null terminator is tolerated
+    pub fn try_into_multi_sz(self) -> Result<Vec<OsString>, UnexpectedRegistryData> {
+        match self.ty {
+            REG_MULTI_SZ => {
+                let wide = self
+                    .data
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect::<Vec<_>>();
+                Ok(wide
+                    .split(|c| *c == 0)
+                    .take_while(|s| !s.is_empty())
+                    .map(OsString::from_wide)
+                    .collect())
+            }
+            actual => Err(UnexpectedRegistryData {
+                expect: REG_MULTI_SZ,
+                actual,
+                data: self.data,
+            }),
+        }
+    }
+
     pub fn try_into_u32(self) -> Result<u32, UnexpectedRegistryData> {
         let mut bytes: [u8; 4] = [0; 4];
         match self.ty {
@@ -126,9 +183,57 @@ impl RegistryData {
     }
 }
 
+/// Expand the environment variables of a string
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/api/processenv/nf-processenv-expandenvironmentstringsw)
+fn expand_environment_strings(src: OsString) -> io::Result<OsString> {
+    let src = to_wide(src);
+    // Probe for the length of the expanded string, including the null terminator
+    let len = unsafe { ExpandEnvironmentStringsW(src.as_ptr(), std::ptr::null_mut(), 0) };
+    if len == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut dst = vec![0u16; len as usize];
+    match unsafe { ExpandEnvironmentStringsW(src.as_ptr(), dst.as_mut_ptr(), len) } {
+        0 => Err(io::Error::last_os_error()),
+        n if n > len => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "environment changed while expanding string",
+        )),
+        n => Ok(OsString::from_wide(&dst[..n as usize - 1])),
+    }
+}
+
+/// Which registry view a 32-bit or 64-bit process opens a key in. By default a 32-bit process on
+/// 64-bit Windows is redirected to the 32-bit view (IE: under Wow6432Node)
+///
+/// [See also](https://learn.microsoft.com/en-us/windows/win32/winprog64/accessing-an-alternate-registry-view)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryView {
+    /// The view of the calling process
+    #[default]
+    Default,
+    /// The 32-bit view, IE: the keys of a 32-bit application
+    Force32,
+    /// The 64-bit view
+    Force64,
+}
+
+impl RegistryView {
+    fn access(self) -> REG_SAM_FLAGS {
+        match self {
+            RegistryView::Default => 0,
+            RegistryView::Force32 => KEY_WOW64_32KEY,
+            RegistryView::Force64 => KEY_WOW64_64KEY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct PredefinedHkey(HKEY);
 impl PredefinedHkey {
     pub const LOCAL_MACHINE: PredefinedHkey = Self(HKEY_LOCAL_MACHINE);
+    pub const CURRENT_USER: PredefinedHkey = Self(HKEY_CURRENT_USER);
 }
 impl From<PredefinedHkey> for HKEY {
     fn from(value: PredefinedHkey) -> Self {
@@ -151,6 +256,9 @@ pub struct HkeyInfo {
     pub max_value_len: usize,
 }
 
+/// The values of a registry key indexed by value name
+pub type RegistryValues = HashMap<OsString, RegistryData>;
+
 /// A subkey within a predefined HKEY
 pub struct Hkey(isize);
 
@@ -193,6 +301,42 @@ impl Hkey {
         }
     }
 
+    /// Set a value under this registry key. The key must be opened with write access, see
+    /// [`create`]
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regsetvalueexw)
+    pub fn set_value<N: Into<OsString>>(&self, name: N, value: &RegistryData) -> io::Result<()> {
+        let name = to_wide(name);
+        let result = unsafe {
+            RegSetValueExW(
+                self.0,
+                name.as_ptr(),
+                0,
+                value.ty,
+                value.data.as_ptr(),
+                value.data.len() as _,
+            )
+        };
+        match result {
+            ERROR_SUCCESS => Ok(()),
+            code => Err(io::Error::from_raw_os_error(code as _)),
+        }
+    }
+
+    /// Delete a value under this registry key. The key must be opened with write access, see
+    /// [`create`]
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regdeletevaluew)
+    pub fn delete_value<N: Into<OsString>>(&self, name: N) -> io::Result<()> {
+        let name = to_wide(name);
+        match unsafe { RegDeleteValueW(self.0, name.as_ptr()) } {
+            ERROR_SUCCESS => Ok(()),
+            code => Err(io::Error::from_raw_os_error(code as _)),
+        }
+    }
+
     /// Return an iterator of values listed under this registry key
     ///
     /// [See also]
@@ -205,6 +349,32 @@ impl Hkey {
             index: 0,
         })
     }
+
+    /// Return an iterator of subkeys listed under this registry key. Each subkey is opened for
+    /// reading relative to this key
+    ///
+    /// [See also]
+    /// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regenumkeyexw)
+    pub fn into_subkeys(self) -> io::Result<HkeySubkeyIter> {
+        let info = self.info()?;
+        Ok(HkeySubkeyIter {
+            hkey: self,
+            info,
+            index: 0,
+        })
+    }
+
+    /// Open a subkey of this key for reading
+    pub fn open_subkey<K: Into<OsString>>(&self, subkey: K) -> io::Result<Hkey> {
+        open_raw(self.0, subkey, RegistryView::Default)
+    }
+}
+
+impl Hkey {
+    /// Read all of the values listed under this registry key
+    pub fn read_values(self) -> io::Result<RegistryValues> {
+        self.into_values()?.collect()
+    }
 }
 
 impl From<Hkey> for HKEY {
@@ -225,6 +395,25 @@ pub struct HkeyValueIter {
     index: usize,
 }
 
+impl HkeyValueIter {
+    /// Only the string values (REG_SZ and REG_EXPAND_SZ) under this key. Values of any other type
+    /// are skipped. Environment variables of a REG_EXPAND_SZ value are not expanded
+    pub fn strings(self) -> impl Iterator<Item = io::Result<(OsString, OsString)>> {
+        self.filter_map(|result| match result {
+            Ok((name, data)) => data.try_into_os_string().ok().map(|s| Ok((name, s))),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Only the REG_DWORD values under this key. Values of any other type are skipped
+    pub fn dwords(self) -> impl Iterator<Item = io::Result<(OsString, u32)>> {
+        self.filter_map(|result| match result {
+            Ok((name, data)) => data.try_into_u32().ok().map(|n| Ok((name, n))),
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
 /// NOTE this is unsound it returns an io::Error but is really a "System error"
 ///
 /// https://learn.microsoft.com/en-us/windows/win32/debug/system-error-codes
@@ -275,20 +464,113 @@ impl Iterator for HkeyValueIter {
     }
 }
 
+pub struct HkeySubkeyIter {
+    hkey: Hkey,
+    info: HkeyInfo,
+    index: usize,
+}
+
+impl Iterator for HkeySubkeyIter {
+    type Item = io::Result<(OsString, Hkey)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Early return when we are empty
+        if self.index == self.info.num_subkeys {
+            return None;
+        }
+        // NOTE we add 2 because wide \0000, the same as value names
+        let mut name_len: u32 = self.info.max_subkey_name_len as u32 + 2;
+        let mut name: Vec<u16> = Vec::with_capacity(name_len as _);
+        let status = unsafe {
+            RegEnumKeyExW(
+                self.hkey.0,
+                self.index as _,
+                name.as_mut_ptr(),
+                &mut name_len,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        match status {
+            ERROR_SUCCESS => {
+                self.index += 1;
+                // Safety: name has been initialized with a null terminated wide char string when
+                // RegEnumKeyExW returns success
+                let name = unsafe { from_wide(name.as_ptr()) };
+                Some(self.hkey.open_subkey(name.clone()).map(|key| (name, key)))
+            }
+            code => Some(Err(io::Error::from_raw_os_error(code as _))),
+        }
+    }
+}
+
 /// Open a subkey associated with a given parent key
 pub fn open<K: Into<OsString>>(parent: PredefinedHkey, subkey: K) -> io::Result<Hkey> {
+    open_with_view(parent, subkey, RegistryView::Default)
+}
+
+/// Open a subkey associated with a given parent key in a specific registry view. Subkeys opened
+/// from the returned key stay in the same view
+///
+/// [See also]
+/// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regopenkeyexw)
+pub fn open_with_view<K: Into<OsString>>(
+    parent: PredefinedHkey,
+    subkey: K,
+    view: RegistryView,
+) -> io::Result<Hkey> {
+    open_raw(parent.into(), subkey, view)
+}
+
+/// Open a subkey associated with any parent key
+fn open_raw<K: Into<OsString>>(parent: HKEY, subkey: K, view: RegistryView) -> io::Result<Hkey> {
     let name = crate::util::wchar::to_wide(subkey);
+    let access = KEY_READ | view.access();
     unsafe {
         let mut key: HKEY = 0;
-        match RegOpenKeyExW(
-            parent.into(),
-            name.as_ptr(),
-            0 as _,
-            KEY_READ as _,
-            &mut key,
-        ) {
+        match RegOpenKeyExW(parent, name.as_ptr(), 0 as _, access, &mut key) {
             ERROR_SUCCESS => Ok(Hkey(key)),
             _ => Err(io::Error::last_os_error()),
         }
     }
 }
+
+/// Open a subkey associated with a given parent key for reading and writing. The subkey is created
+/// if it does not exist
+///
+/// [See also]
+/// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regcreatekeyexw)
+pub fn create<K: Into<OsString>>(parent: PredefinedHkey, subkey: K) -> io::Result<Hkey> {
+    let name = crate::util::wchar::to_wide(subkey);
+    let mut key: HKEY = 0;
+    let result = unsafe {
+        RegCreateKeyExW(
+            parent.into(),
+            name.as_ptr(),
+            0,
+            std::ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_READ | KEY_WRITE,
+            std::ptr::null(),
+            &mut key,
+            std::ptr::null_mut(),
+        )
+    };
+    match result {
+        ERROR_SUCCESS => Ok(Hkey(key)),
+        code => Err(io::Error::from_raw_os_error(code as _)),
+    }
+}
+
+/// Delete a subkey and its values. The subkey must not have subkeys of its own
+///
+/// [See also]
+/// (https://learn.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regdeletekeyw)
+pub fn delete<K: Into<OsString>>(parent: PredefinedHkey, subkey: K) -> io::Result<()> {
+    let name = crate::util::wchar::to_wide(subkey);
+    match unsafe { RegDeleteKeyW(parent.into(), name.as_ptr()) } {
+        ERROR_SUCCESS => Ok(()),
+        code => Err(io::Error::from_raw_os_error(code as _)),
+    }
+}
@@ -2,7 +2,7 @@ use futures::FutureExt;
 
 use super::guid::Guid;
 use super::wait::{self, Event, EventInitialState, EventListener, EventReset, WaitError};
-use super::wchar::from_wide;
+use super::wchar::{from_nwide, from_wide};
 
 #[test]
 fn service_test_util_wchar_arr() {
@@ -22,6 +22,23 @@ fn service_test_util_wchar() {
     assert_eq!("Unicode", term);
 }
 
+#[test]
+fn service_test_guid_random() {
+    let guid = Guid::random().unwrap();
+    assert!(guid != Guid::random().unwrap());
+    assert!(guid == Guid::new(guid.to_string()).unwrap());
+}
+
+#[test]
+fn service_test_guid_hash() {
+    let a = Guid::new("a9214533-3f5f-475b-8140-cb96b289270b").unwrap();
+    let b = Guid::new("A9214533-3F5F-475B-8140-CB96B289270B").unwrap();
+    let mut set = std::collections::HashSet::new();
+    set.insert(a);
+    assert!(!set.insert(b));
+    assert_eq!(1, set.len());
+}
+
 #[test]
 fn service_test_guid_from_str() {
     let ok = Guid::new("a9214533-3f5f-475b-8140-cb96b289270b");
@@ -39,12 +56,13 @@ fn service_test_util_wait() {
     // Create an anonymous manually resetable event
     let ev = Event::anonymous(EventReset::Manual, EventInitialState::Unset).unwrap();
     let pool = EventListener::new().unwrap();
-    let mut fut = pool.start(&ev, None);
+    let mut fut = pool.start(&ev, None).unwrap();
 
     // Make sure cannot wait twice and err InProgres
     let err = pool.restart(&ev, None);
     assert!(err.is_err());
     assert_eq!(WaitError::InProgress, err.unwrap_err());
+    assert_eq!(Some(WaitError::InProgress), pool.start(&ev, None).err());
 
     // Make sure we are pending
     let poll = fut.poll_unpin(&mut cx);
@@ -73,6 +91,27 @@ fn service_test_util_wait() {
     assert!(poll.is_ready());
 }
 
+#[test]
+fn service_test_util_wait_dropped_waitable() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Drop the event while the listener is waiting on it
+    let name = "msft-service-test-wait-dropped-waitable";
+    let ev = Event::named(name, EventReset::Manual, EventInitialState::Unset).unwrap();
+    let listener = EventListener::new().unwrap();
+    let mut fut = listener.start(&ev, None).unwrap();
+    drop(ev);
+    assert!(fut.poll_unpin(&mut cx).is_pending());
+
+    // The listener keeps the event alive, so opening it by name finds the same event
+    let ev = Event::named(name, EventReset::Manual, EventInitialState::Unset).unwrap();
+    ev.set().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    assert_eq!(std::task::Poll::Ready(Ok(())), fut.poll_unpin(&mut cx));
+}
+
 #[test]
 fn service_test_util_oneshot() {
     // Create a test waker
@@ -93,3 +132,300 @@ fn service_test_util_oneshot() {
     let poll = receiver.poll_unpin(&mut cx);
     assert!(poll.is_ready());
 }
+
+#[test]
+fn service_test_util_oneshot_timeout() {
+    // Create a test waker
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = std::task::Context::from_waker(waker);
+
+    // Create a channel signal which is never set
+    let (_sender, mut receiver) =
+        wait::oneshot_timeout(std::time::Duration::from_millis(10)).unwrap();
+
+    // Make sure we are pending before the timeout
+    let poll = receiver.poll_unpin(&mut cx);
+    assert!(poll.is_pending());
+
+    // Make sure we timeout
+    // NOTE we set the time delay to allow kernel some time to drive our future
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let poll = receiver.poll_unpin(&mut cx);
+    assert_eq!(std::task::Poll::Ready(Err(WaitError::Timeout)), poll);
+}
+
+#[test]
+fn service_test_hkey_expand_sz() {
+    use super::hkey::RegistryData;
+    use windows_sys::Win32::System::Registry::REG_EXPAND_SZ;
+    let data = "%SystemRoot%\\System32\0"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let expanded = RegistryData::from_data(REG_EXPAND_SZ, data)
+        .try_into_expanded_os_string()
+        .unwrap();
+    let mut expect = std::env::var_os("SystemRoot").unwrap();
+    expect.push("\\System32");
+    assert_eq!(expect, expanded);
+}
+
+#[test]
+fn service_test_util_nwide() {
+    // UTF-16 encoding for "Unicode" with and without a null terminator
+    let s: &[u8] = b"\x55\x00\x6E\x00\x69\x00\x63\x00\x6f\x00\x64\x00\x65\x00\x00\x00";
+    let term = unsafe { from_nwide(s.as_ptr() as *const _, s.len()) };
+    assert_eq!("Unicode", term);
+    let term = unsafe { from_nwide(s.as_ptr() as *const _, s.len() - 2) };
+    assert_eq!("Unicode", term);
+}
+
+#[test]
+fn service_test_hkey_sz_not_terminated() {
+    use super::hkey::RegistryData;
+    use windows_sys::Win32::System::Registry::REG_SZ;
+    let data = "COM3".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let s = RegistryData::from_data(REG_SZ, data)
+        .try_into_os_string()
+        .unwrap();
+    assert_eq!("COM3", s);
+}
+
+#[test]
+fn service_test_hkey_multi_sz() {
+    use super::hkey::RegistryData;
+    use windows_sys::Win32::System::Registry::{REG_MULTI_SZ, REG_SZ};
+    let encode = |s: &str| s.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+    // Two strings with and without the final double null terminator
+    let list = RegistryData::from_data(REG_MULTI_SZ, encode("RpcSs\0PlugPlay\0\0"))
+        .try_into_multi_sz()
+        .unwrap();
+    assert_eq!(vec!["RpcSs", "PlugPlay"], list);
+    let list = RegistryData::from_data(REG_MULTI_SZ, encode("RpcSs\0PlugPlay"))
+        .try_into_multi_sz()
+        .unwrap();
+    assert_eq!(vec!["RpcSs", "PlugPlay"], list);
+
+    // An empty list
+    let list = RegistryData::from_data(REG_MULTI_SZ, encode("\0"))
+        .try_into_multi_sz()
+        .unwrap();
+    assert!(list.is_empty());
+    let list = RegistryData::from_data(REG_MULTI_SZ, Vec::new())
+        .try_into_multi_sz()
+        .unwrap();
+    assert!(list.is_empty());
+
+    // Other types are rejected
+    let err = RegistryData::from_data(REG_SZ, encode("RpcSs")).try_into_multi_sz();
+    assert!(err.is_err());
+}
+
+#[test]
+fn service_test_hkey_set_value() {
+    use super::hkey::{self, PredefinedHkey, RegistryData};
+    let subkey = "SOFTWARE\\msft-service-test-hkey-set-value";
+    let key = hkey::create(PredefinedHkey::CURRENT_USER, subkey).unwrap();
+    key.set_value("name", &RegistryData::sz("COM3")).unwrap();
+    key.set_value("baud", &RegistryData::dword(115200)).unwrap();
+
+    // Read the values back
+    let mut values = hkey::open(PredefinedHkey::CURRENT_USER, subkey)
+        .unwrap()
+        .read_values()
+        .unwrap();
+    let name = values.remove(std::ffi::OsStr::new("name")).unwrap();
+    let baud = values.remove(std::ffi::OsStr::new("baud")).unwrap();
+    assert_eq!("COM3", name.try_into_os_string().unwrap());
+    assert_eq!(115200, baud.try_into_u32().unwrap());
+
+    // Cleanup
+    key.delete_value("name").unwrap();
+    key.delete_value("baud").unwrap();
+    drop(key);
+    hkey::delete(PredefinedHkey::CURRENT_USER, subkey).unwrap();
+}
+
+#[test]
+fn service_test_hkey_values_by_type() {
+    use super::hkey::{self, PredefinedHkey, RegistryData};
+    let subkey = "SOFTWARE\\msft-service-test-hkey-values-by-type";
+    let key = hkey::create(PredefinedHkey::CURRENT_USER, subkey).unwrap();
+    key.set_value("name", &RegistryData::sz("COM3")).unwrap();
+    key.set_value("baud", &RegistryData::dword(115200)).unwrap();
+
+    // Each adapter skips the values of the other type
+    let open = || hkey::open(PredefinedHkey::CURRENT_USER, subkey).unwrap();
+    let strings = open()
+        .into_values()
+        .unwrap()
+        .strings()
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    let dwords = open()
+        .into_values()
+        .unwrap()
+        .dwords()
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    let name = |s: &str| std::ffi::OsString::from(s);
+    assert_eq!(vec![(name("name"), name("COM3"))], strings);
+    assert_eq!(vec![(name("baud"), 115200)], dwords);
+
+    // Cleanup
+    drop(key);
+    hkey::delete(PredefinedHkey::CURRENT_USER, subkey).unwrap();
+}
+
+#[test]
+fn service_test_hkey_open_with_view() {
+    use super::hkey::{self, PredefinedHkey, RegistryView};
+    let subkey = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion";
+    for view in [
+        RegistryView::Default,
+        RegistryView::Force32,
+        RegistryView::Force64,
+    ] {
+        assert!(hkey::open_with_view(PredefinedHkey::LOCAL_MACHINE, subkey, view).is_ok());
+    }
+}
+
+#[test]
+fn service_test_hkey_subkeys() {
+    use super::hkey::{self, PredefinedHkey};
+    let parent = "SOFTWARE\\msft-service-test-hkey-subkeys";
+    let child = "SOFTWARE\\msft-service-test-hkey-subkeys\\child";
+    drop(hkey::create(PredefinedHkey::CURRENT_USER, child).unwrap());
+
+    let names = hkey::open(PredefinedHkey::CURRENT_USER, parent)
+        .unwrap()
+        .into_subkeys()
+        .unwrap()
+        .map(|subkey| subkey.map(|(name, _key)| name))
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(vec![std::ffi::OsString::from("child")], names);
+
+    // Cleanup
+    hkey::delete(PredefinedHkey::CURRENT_USER, child).unwrap();
+    hkey::delete(PredefinedHkey::CURRENT_USER, parent).unwrap();
+}
+
+#[test]
+fn service_test_device_interface_path() {
+    use super::hkey::RegistryData;
+    use crate::device::UsbDevice;
+    let path =
+        "\\\\?\\usb#vid_2341&pid_0043#7523733353635111A1F2#{86e0d1e0-8089-11d0-9ce4-08003e301f73}";
+    let device = UsbDevice::try_from(RegistryData::sz(path)).unwrap();
+    assert_eq!(path, *device.interface_path());
+    assert_eq!(
+        "usb\\vid_2341&pid_0043\\7523733353635111A1F2",
+        *device.instance_id()
+    );
+}
+
+#[test]
+fn service_test_device_vid_pid() {
+    use super::hkey::RegistryData;
+    use crate::device::UsbVidPid;
+    let parse = |path: &str| UsbVidPid::try_from(RegistryData::sz(path));
+
+    // Generic USB serial device
+    let ids = parse(
+        "\\\\?\\usb#vid_2341&pid_0043#7523733353635111A1F2#{86e0d1e0-8089-11d0-9ce4-08003e301f73}",
+    )
+    .unwrap();
+    assert!(ids.matches("2341", "0043"));
+
+    // CH340
+    let ids =
+        parse("\\\\?\\USB#VID_1A86&PID_7523#5&2ba8b2a7&0&2#{86e0d1e0-8089-11d0-9ce4-08003e301f73}")
+            .unwrap();
+    assert!(ids.matches("1A86", "7523"));
+
+    // FTDI
+    let ids = parse(
+        "\\\\?\\FTDIBUS#VID_0403+PID_6001+A50285BIA#0000#{86e0d1e0-8089-11d0-9ce4-08003e301f73}",
+    )
+    .unwrap();
+    assert!(ids.matches("0403", "6001"));
+
+    // Short and unknown formats are errors, not panics
+    assert!(parse("USB\\VID_12").is_err());
+    assert!(parse("\\\\?\\ACPI#PNP0501#1#{86e0d1e0-8089-11d0-9ce4-08003e301f73}").is_err());
+}
+
+#[test]
+fn service_test_device_debounce() {
+    use crate::device::{DeviceStreamExt, PlugEvent};
+    use futures::{executor::block_on, stream, StreamExt};
+    use std::time::{Duration, Instant};
+    let window = Duration::from_millis(50);
+    let events = vec![
+        PlugEvent::Unplug("COM3".into()),
+        PlugEvent::Plug("COM3".into()),
+        PlugEvent::Unplug("COM4".into()),
+        PlugEvent::Plug("COM5".into()),
+    ];
+
+    // NOTE the inner stream stays open so the late unplug must come from the timer
+    let start = Instant::now();
+    let mut debounced = stream::iter(events)
+        .chain(stream::pending())
+        .debounce(window)
+        .unwrap();
+
+    // The COM3 bounce is suppressed and the COM5 plug passes through
+    let plug = block_on(debounced.next());
+    assert!(matches!(plug, Some(PlugEvent::Plug(port)) if port == "COM5"));
+
+    // The COM4 unplug is genuine and fires once the window elapses
+    let unplug = block_on(debounced.next());
+    assert!(matches!(unplug, Some(PlugEvent::Unplug(port)) if port == "COM4"));
+    assert!(start.elapsed() >= window);
+
+    // Nothing else is pending
+    assert!(debounced.next().now_or_never().is_none());
+}
+
+#[test]
+fn service_test_message_stream_terminal_not_dropped() {
+    use crate::message::{service_control_message_handler, ServiceMessageEx, ServiceMessageStream};
+    use futures::{executor::block_on, StreamExt};
+    use windows_sys::Win32::System::Services::{
+        SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_PARAMCHANGE, SERVICE_CONTROL_STOP,
+    };
+    let mut stream = ServiceMessageStream::with_capacity(1);
+    let send = |control| unsafe {
+        service_control_message_handler(control, 0, std::ptr::null_mut(), stream.state() as _)
+    };
+
+    // Fill the queue past capacity, with more messages after the stop
+    send(SERVICE_CONTROL_INTERROGATE);
+    send(SERVICE_CONTROL_STOP);
+    send(SERVICE_CONTROL_INTERROGATE);
+    send(SERVICE_CONTROL_PARAMCHANGE);
+
+    // The newest message survives, and the stop still ends the stream
+    let messages = block_on(stream.by_ref().collect::<Vec<_>>());
+    assert!(matches!(
+        messages[..],
+        [ServiceMessageEx::ParamChange, ServiceMessageEx::Stop]
+    ));
+    assert!(block_on(stream.next()).is_none());
+}
+
+#[test]
+fn service_test_volume_event() {
+    use crate::message::VolumeEvent;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{DBTF_NET, DEV_BROADCAST_VOLUME};
+    let mut volume: DEV_BROADCAST_VOLUME = unsafe { std::mem::zeroed() };
+    volume.dbcv_unitmask = 0b101 | 1 << 25;
+    volume.dbcv_flags = DBTF_NET;
+    let event = VolumeEvent::from(volume);
+    assert_eq!(vec!['A', 'C', 'Z'], event.drives);
+    assert!(!event.is_media);
+    assert!(event.is_net);
+}
@@ -1,12 +1,13 @@
 //! wait
 
+use crate::runtime::common::{abort_on_unwind, ThreadpoolCallbackEnvironment};
 use parking_lot::Mutex;
 use std::{
     ffi::{c_void, OsString},
     future::Future,
     io,
     os::windows::{
-        io::{AsRawHandle, HandleOrNull, OwnedHandle, RawHandle},
+        io::{AsRawHandle, BorrowedHandle, HandleOrNull, OwnedHandle, RawHandle},
         prelude::*,
     },
     pin::Pin,
@@ -14,6 +15,7 @@ use std::{
     task::{Context, Poll, Waker},
     time::Duration,
 };
+use tracing::error;
 use windows_sys::Win32::{
     Foundation::{FALSE, FILETIME, TRUE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT},
     System::Threading::{
@@ -39,6 +41,16 @@ pub enum WaitError {
     /// Already waiting for the waitable object
     #[error("wait already in progress")]
     InProgress,
+    /// The waitable object is a mutex which was not released by the thread that owned it before
+    /// the thread terminated
+    #[error("wait abandoned")]
+    Abandoned = WAIT_ABANDONED,
+    /// The wait resolved with a result which is not documented for a threadpool wait
+    #[error("unexpected wait result {0}")]
+    Unexpected(u32),
+    /// The wait could not be started, IE: the waitable handle could not be duplicated
+    #[error("wait io error => {0}")]
+    Io(io::ErrorKind),
 }
 
 /// Waitable object as per windows
@@ -70,27 +82,47 @@ impl Drop for WaitPool {
 impl WaitPool {
     /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-createthreadpoolwait
     pub fn new(cx: *mut c_void, callback: WaitCallback) -> io::Result<Self> {
-        let result = unsafe { CreateThreadpoolWait(Some(callback), cx, std::ptr::null()) };
+        Self::new_with(None, cx, callback)
+    }
+
+    /// Like [`WaitPool::new`] except the callbacks run on the threadpool of the environment
+    pub fn with_environment(
+        env: &ThreadpoolCallbackEnvironment,
+        cx: *mut c_void,
+        callback: WaitCallback,
+    ) -> io::Result<Self> {
+        Self::new_with(Some(env), cx, callback)
+    }
+
+    fn new_with(
+        maybe_env: Option<&ThreadpoolCallbackEnvironment>,
+        cx: *mut c_void,
+        callback: WaitCallback,
+    ) -> io::Result<Self> {
+        let env = maybe_env.map_or_else(std::ptr::null, |env| env.as_raw());
+        let result = unsafe { CreateThreadpoolWait(Some(callback), cx, env) };
         match result {
             0 => Err(io::Error::last_os_error()),
             handle => Ok(WaitPool(handle)),
         }
     }
 
-    /// Set a wait object on a threadpool which will trigger a wait callback
+    /// Set a wait object on a threadpool which will trigger a wait callback. The timeout is
+    /// relative to when the wait is started.
     ///
     /// https://learn.microsoft.com/en-us/windows/win32/api/threadpoolapiset/nf-threadpoolapiset-setthreadpoolwait
     pub fn start<W: Waitable>(&self, waitable: &W, timeout: Option<Duration>) {
-        let ft = timeout
-            .map(|to| {
-                let ms = to.as_millis();
-                &FILETIME {
-                    dwHighDateTime: (ms >> 32) as u32,
-                    dwLowDateTime: (ms & 0xFFFFFFFF) as u32,
-                } as *const _
-            })
-            .unwrap_or_else(std::ptr::null);
-        unsafe { SetThreadpoolWait(self.0, waitable.as_raw_handle() as _, ft) };
+        // NOTE a negative FILETIME is a relative timeout in 100ns intervals. A positive FILETIME
+        // is an absolute time since 1601 which has always passed for small values
+        let ft = timeout.map(|to| {
+            let ticks = -i64::try_from(to.as_nanos() / 100).unwrap_or(i64::MAX);
+            FILETIME {
+                dwHighDateTime: (ticks >> 32) as u32,
+                dwLowDateTime: (ticks & 0xFFFFFFFF) as u32,
+            }
+        });
+        let ptr = ft.as_ref().map_or_else(std::ptr::null, |ft| ft as *const _);
+        unsafe { SetThreadpoolWait(self.0, waitable.as_raw_handle() as _, ptr) };
     }
 
     /// The wait object will cease to queue new callbacks. Callbacks already queued will still fire
@@ -152,22 +184,34 @@ impl Future for Waiting {
     }
 }
 
+/// Map the result of a threadpool wait callback. Results which are not documented are logged
+/// rather than mistaken for an abandoned mutex
+fn wait_result(waitresult: u32) -> WaitResult {
+    match waitresult {
+        WAIT_OBJECT_0 => Ok(()),
+        WAIT_TIMEOUT => Err(WaitError::Timeout),
+        WAIT_ABANDONED => Err(WaitError::Abandoned),
+        unexpected => {
+            error!(unexpected, "unexpected wait result");
+            Err(WaitError::Unexpected(unexpected))
+        }
+    }
+}
+
 unsafe extern "system" fn wait_callback(
     _instance: PTP_CALLBACK_INSTANCE,
     context: *mut c_void,
     _wait: PTP_WAIT,
     waitresult: u32,
 ) {
-    let state = &*(context as *const Mutex<WaitState>);
-    let mut shared = state.lock();
-    shared.result = match waitresult {
-        WAIT_OBJECT_0 => Some(Ok(())),
-        WAIT_TIMEOUT => Some(Err(WaitError::Timeout)),
-        _ => panic!("Unsupported kernel argument passed to wait callback!"),
-    };
-    if let Some(waker) = shared.waker.as_ref() {
-        waker.wake_by_ref()
-    }
+    abort_on_unwind("wait_callback", || {
+        let state = &*(context as *const Mutex<WaitState>);
+        let mut shared = state.lock();
+        shared.result = Some(wait_result(waitresult));
+        if let Some(waker) = shared.waker.as_ref() {
+            waker.wake_by_ref()
+        }
+    })
 }
 
 /// Windows CreateEvent creation argument
@@ -178,7 +222,7 @@ unsafe extern "system" fn wait_callback(
 /// event object, and the system automatically resets the event state to
 /// nonsignaled after a single waiting thread has been released.
 #[repr(i32)]
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventReset {
     Manual = TRUE,
     Automatic = FALSE,
@@ -195,7 +239,11 @@ pub enum EventInitialState {
 }
 
 #[derive(Debug)]
-pub struct Event(OwnedHandle);
+pub struct Event {
+    handle: OwnedHandle,
+    /// Windows has no getter for the reset mode so we remember the mode used at creation
+    reset: EventReset,
+}
 
 impl Event {
     /// Create a system event
@@ -230,7 +278,12 @@ impl Event {
             let handle = HandleOrNull::from_raw_handle(raw as _);
             OwnedHandle::try_from(handle).map_err(|_| io::Error::last_os_error())
         }
-        .map(Self)
+        .map(|handle| Self { handle, reset })
+    }
+
+    /// The reset mode passed when the event was created
+    pub fn reset_mode(&self) -> EventReset {
+        self.reset
     }
 
     pub fn set(&self) -> io::Result<()> {
@@ -247,6 +300,10 @@ impl Event {
         }
     }
 
+    /// Block the calling thread until the event is signaled or the timeout elapses.
+    ///
+    /// NOTE this blocks the thread and will stall an async executor. In async contexts use
+    /// [`Event::wait_async`] instead
     pub fn wait(&self, duration: Option<Duration>) -> Result<(), EventError> {
         let dur: u32 = duration.map(|d| d.as_millis() as _).unwrap_or(INFINITE);
         match unsafe { WaitForSingleObject(self.as_raw_handle() as _, dur as _) } {
@@ -257,13 +314,23 @@ impl Event {
             _ => Err(EventError::Io(io::Error::last_os_error())),
         }
     }
+
+    /// Wait for the event on the threadpool without blocking the calling thread. See
+    /// [`EventListener::start`]
+    pub fn wait_async(
+        &self,
+        listener: &EventListener,
+        timeout: Option<Duration>,
+    ) -> Result<Waiting, WaitError> {
+        listener.start(self, timeout)
+    }
 }
 
 impl Waitable for Event {}
 
 impl AsRawHandle for Event {
     fn as_raw_handle(&self) -> RawHandle {
-        self.0.as_raw_handle()
+        self.handle.as_raw_handle()
     }
 }
 
@@ -289,10 +356,16 @@ pub enum EventError {
 /// and waits for callbacks to finish executing. Then the pointers are dropped.
 ///
 /// Safety: DO NOT CHANGE ORDER IN STRUCT (RFC 1857)
+///
+/// The listener waits on its own duplicate of the waitable handle, so the caller may close the
+/// waitable while a wait is pending. Closing a handle a threadpool wait refers to is undefined
+/// behavior. The duplicate is closed after the pool, when the listener is dropped or restarted.
 #[derive(Debug)]
 pub struct EventListener {
     /// A pool of workers to wait on waitable objects. See [`self::WaitPool`]. NOTE the
     pool: WaitPool,
+    /// The duplicate of the handle the pool is waiting on
+    held: Mutex<Option<HeldHandle>>,
     /// Shared state between the waitable worker callbacks and future waiting for event
     state: Arc<Mutex<WaitState>>,
 }
@@ -300,32 +373,76 @@ pub struct EventListener {
 impl EventListener {
     pub fn new() -> io::Result<Self> {
         let state = Arc::new(Mutex::new(WaitState::default()));
-        WaitPool::new(Arc::as_ptr(&state) as _, wait_callback).map(|pool| Self { pool, state })
+        WaitPool::new(Arc::as_ptr(&state) as _, wait_callback).map(|pool| Self {
+            pool,
+            held: Mutex::new(None),
+            state,
+        })
     }
 
-    pub fn start<W>(&self, waitable: &W, timeout: Option<Duration>) -> Waiting
+    /// Like [`EventListener::new`] except the waits run on the threadpool of the environment
+    pub fn with_environment(env: &ThreadpoolCallbackEnvironment) -> io::Result<Self> {
+        let state = Arc::new(Mutex::new(WaitState::default()));
+        WaitPool::with_environment(env, Arc::as_ptr(&state) as _, wait_callback).map(|pool| Self {
+            pool,
+            held: Mutex::new(None),
+            state,
+        })
+    }
+
+    /// Start waiting on a duplicate of the waitable's handle. See [`EventListener`]
+    ///
+    /// Errors with [`WaitError::InProgress`] if a wait is already pending, or with
+    /// [`WaitError::Io`] if the handle can not be duplicated
+    pub fn start<W>(&self, waitable: &W, timeout: Option<Duration>) -> Result<Waiting, WaitError>
     where
         W: Waitable,
     {
         let state = self.state.lock();
         if let None = state.result {
-            self.pool.start(waitable, timeout);
-            Waiting(Arc::clone(&self.state))
+            if self.held.lock().is_some() {
+                return Err(WaitError::InProgress);
+            }
+            self.hold_and_start(waitable, timeout)?;
+            Ok(Waiting(Arc::clone(&self.state)))
         } else {
             panic!("Cannot start waiting more than once! use restart instead")
         }
     }
 
+    /// Start a new wait once the previous wait has resolved. See [`EventListener::start`]
     pub fn restart<W>(&self, waitable: &W, timeout: Option<Duration>) -> Result<Waiting, WaitError>
     where
         W: Waitable,
     {
         let mut state = self.state.lock();
-        let _result = state.result.take().ok_or(WaitError::InProgress)?;
-        self.pool.start(waitable, timeout);
+        if let None = state.result {
+            return Err(WaitError::InProgress);
+        }
+        // NOTE the previous result is kept when the wait can not be started
+        self.hold_and_start(waitable, timeout)?;
+        state.result = None;
         Ok(Waiting(Arc::clone(&self.state)))
     }
 
+    fn hold_and_start<W: Waitable>(
+        &self,
+        waitable: &W,
+        timeout: Option<Duration>,
+    ) -> Result<(), WaitError> {
+        // Safety: the waitable is borrowed, so the raw handle is valid for this call
+        let borrowed = unsafe { BorrowedHandle::borrow_raw(waitable.as_raw_handle()) };
+        let held = borrowed
+            .try_clone_to_owned()
+            .map(HeldHandle)
+            .map_err(|e| WaitError::Io(e.kind()))?;
+        self.pool.start(&held, timeout);
+        // NOTE a wait is only started when no wait is pending. The previous wait has resolved or
+        // was stopped, so the pool no longer refers to the previous duplicate
+        *self.held.lock() = Some(held);
+        Ok(())
+    }
+
     pub fn cancel(&self) -> &Self {
         self.pool.stop();
         let mut state = self.state.lock();
@@ -340,6 +457,18 @@ impl EventListener {
     }
 }
 
+/// A duplicate of a waitable handle owned by an [`EventListener`]
+#[derive(Debug)]
+struct HeldHandle(OwnedHandle);
+
+impl Waitable for HeldHandle {}
+
+impl AsRawHandle for HeldHandle {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
 #[derive(Debug)]
 pub struct Receiver {
     #[allow(unused)]
@@ -388,10 +517,20 @@ impl Sender {
 }
 
 pub fn oneshot() -> io::Result<(Sender, Receiver)> {
+    oneshot_with(None)
+}
+
+/// Like [`oneshot`] except the [`Receiver`] resolves with [`WaitError::Timeout`] if the
+/// [`Sender`] is not set before the timeout elapses
+pub fn oneshot_timeout(timeout: Duration) -> io::Result<(Sender, Receiver)> {
+    oneshot_with(Some(timeout))
+}
+
+fn oneshot_with(timeout: Option<Duration>) -> io::Result<(Sender, Receiver)> {
     let event = Event::anonymous(EventReset::Manual, EventInitialState::Unset)?;
     let state = Arc::new((Mutex::new(WaitState::default()), event));
     let pool = WaitPool::new(Arc::as_ptr(&state) as _, oneshot_callback)?;
-    pool.start(&state.1, None);
+    pool.start(&state.1, timeout);
     let sender = Sender { state };
     let receiver = Receiver {
         state: Arc::clone(&sender.state),
@@ -406,14 +545,12 @@ unsafe extern "system" fn oneshot_callback(
     _wait: PTP_WAIT,
     waitresult: u32,
 ) {
-    let state = &*(context as *const (Mutex<WaitState>, Event));
-    let mut shared = state.0.lock();
-    shared.result = match waitresult {
-        WAIT_OBJECT_0 => Some(Ok(())),
-        WAIT_TIMEOUT => Some(Err(WaitError::Timeout)),
-        _ => panic!("Unsupported kernel argument passed to wait callback!"),
-    };
-    if let Some(waker) = shared.waker.as_ref() {
-        waker.wake_by_ref()
-    }
+    abort_on_unwind("oneshot_callback", || {
+        let state = &*(context as *const (Mutex<WaitState>, Event));
+        let mut shared = state.0.lock();
+        shared.result = Some(wait_result(waitresult));
+        if let Some(waker) = shared.waker.as_ref() {
+            waker.wake_by_ref()
+        }
+    })
 }
@@ -21,6 +21,21 @@ pub unsafe fn from_wide(ptr: *const u16) -> OsString {
     OsString::from_wide(std::slice::from_raw_parts(ptr, len))
 }
 
+/// Convert a u16 array of a known length into an OsString. Unlike [`from_wide`] the array does
+/// not need to be null terminated. A single trailing null is trimmed if present.
+///
+/// Safety: The pointer must be valid for reads of `len` bytes. The pointer does not need to be
+/// aligned
+pub unsafe fn from_nwide(ptr: *const u16, len: usize) -> OsString {
+    let mut wide = (0..len / std::mem::size_of::<u16>())
+        .map(|i| ptr.add(i).read_unaligned())
+        .collect::<Vec<u16>>();
+    if let Some(0) = wide.last() {
+        wide.pop();
+    }
+    OsString::from_wide(&wide)
+}
+
 #[macro_export]
 macro_rules! get_window_text {
     ($hwnd:expr, $max:expr) => {{